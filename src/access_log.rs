@@ -0,0 +1,254 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use solarized::{
+    print_fancy,
+    BLUE, RED,
+    BOLD,
+    PrintMode::NewLine,
+};
+
+use crate::config::Config;
+use crate::routes::SharedSites;
+
+/// Who made the request, as found by the guard and forum session checks that
+/// ran while handling it. The log never looks anyone up itself, so a static
+/// asset hit costs no database work.
+#[derive(Default)]
+struct Identity {
+    guard_email: Option<String>,
+    forum_user: Option<String>,
+}
+
+tokio::task_local! {
+    static IDENTITY: Mutex<Identity>;
+}
+
+/// Record the auth guard email a session check resolved for this request.
+pub fn note_guard_email(email: &str) {
+    let _ = IDENTITY.try_with(|id| id.lock().unwrap().guard_email = Some(email.to_string()));
+}
+
+/// Record the forum username a session check resolved for this request.
+pub fn note_forum_user(username: &str) {
+    let _ = IDENTITY.try_with(|id| id.lock().unwrap().forum_user = Some(username.to_string()));
+}
+
+fn log_err(msg: &str) {
+    print_fancy(&[("[access_log] ", BLUE, vec![]), (msg, RED, vec![BOLD])], NewLine);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Combined,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Rotation {
+    Never,
+    Daily,
+    Size(u64),
+}
+
+/// Handle the middleware sends finished log lines to; a background task owns
+/// the file and does the rotation.
+#[derive(Clone)]
+pub struct AccessLog {
+    format: Format,
+    lines: mpsc::UnboundedSender<String>,
+    sites: SharedSites,
+}
+
+impl AccessLog {
+    /// Start the writer task when `access_log` is set. Settings are read once at
+    /// startup, like the listener settings.
+    pub fn start(config: &Config, sites: SharedSites) -> Option<Self> {
+        let path = crate::paths::get().in_data_dir(config.access_log.as_ref()?);
+        let format = match config.access_log_format.as_str() {
+            "json" => Format::Json,
+            _ => Format::Combined,
+        };
+        let rotation = match config.access_log_rotate.as_str() {
+            "daily" => Rotation::Daily,
+            "size" => Rotation::Size(config.access_log_max_bytes.max(1)),
+            _ => Rotation::Never,
+        };
+        let (lines, rx) = mpsc::unbounded_channel();
+        tokio::spawn(writer(path, rotation, config.access_log_keep, rx));
+        Some(Self { format, lines, sites })
+    }
+}
+
+async fn open(path: &Path) -> std::io::Result<(File, u64)> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path).await?;
+    let size = file.metadata().await?.len();
+    Ok((file, size))
+}
+
+/// Rename the live file to `<name>.<suffix>` and delete the oldest rotated
+/// files beyond `keep`. Suffixes sort chronologically.
+async fn rotate(path: &Path, suffix: &str, keep: usize) {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{suffix}"));
+    if let Err(e) = tokio::fs::rename(path, &rotated).await {
+        log_err(&format!("could not rotate {}: {e}", path.display()));
+        return;
+    }
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else {
+        return;
+    };
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let prefix = format!("{name}.");
+    let mut old = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.file_name().to_str().is_some_and(|n| n.starts_with(&prefix)) {
+                old.push(entry.path());
+            }
+        }
+    }
+    old.sort();
+    let excess = old.len().saturating_sub(keep);
+    for stale in &old[..excess] {
+        let _ = tokio::fs::remove_file(stale).await;
+    }
+}
+
+async fn writer(path: PathBuf, rotation: Rotation, keep: usize, mut rx: mpsc::UnboundedReceiver<String>) {
+    let (mut file, mut size) = match open(&path).await {
+        Ok(opened) => opened,
+        Err(e) => {
+            log_err(&format!("could not open {}: {e}; access logging disabled", path.display()));
+            return;
+        }
+    };
+    let mut day: NaiveDate = Utc::now().date_naive();
+    while let Some(line) = rx.recv().await {
+        let now = Utc::now();
+        let suffix = match rotation {
+            Rotation::Daily if now.date_naive() != day => Some(day.format("%Y-%m-%d").to_string()),
+            Rotation::Size(max) if size > 0 && size + line.len() as u64 > max => {
+                Some(now.format("%Y%m%d-%H%M%S").to_string())
+            }
+            _ => None,
+        };
+        day = now.date_naive();
+        if let Some(suffix) = suffix {
+            let _ = file.flush().await;
+            rotate(&path, &suffix, keep).await;
+            match open(&path).await {
+                Ok((f, s)) => {
+                    file = f;
+                    size = s;
+                }
+                Err(e) => {
+                    log_err(&format!("could not reopen {}: {e}; access logging disabled", path.display()));
+                    return;
+                }
+            }
+        }
+        if let Err(e) = file.write_all(line.as_bytes()).await {
+            log_err(&format!("write to {} failed: {e}", path.display()));
+        }
+        size += line.len() as u64;
+    }
+}
+
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Outer-router middleware: one line per request with client IP, identity,
+/// host, request line, status, size and latency.
+pub async fn record(State(log): State<AccessLog>, req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let time: DateTime<Utc> = Utc::now();
+    let header_str = |name: header::HeaderName| {
+        req.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or("").to_string()
+    };
+    let host = header_str(header::HOST);
+    let referer = header_str(header::REFERER);
+    let user_agent = header_str(header::USER_AGENT);
+    let method = req.method().to_string();
+    let uri = req.uri().to_string();
+    let version = format!("{:?}", req.version());
+    let current = log.sites.read().await.clone();
+    let client_ip = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| current.client_ip(addr.ip(), req.headers()).to_string())
+        .unwrap_or_else(|| "-".to_string());
+
+    let (response, found) = IDENTITY
+        .scope(Mutex::new(Identity::default()), async {
+            let response = next.run(req).await;
+            let found = IDENTITY.with(|id| std::mem::take(&mut *id.lock().unwrap()));
+            (response, found)
+        })
+        .await;
+
+    let elapsed_ms = start.elapsed().as_secs_f64() * 1000.0;
+    let status = response.status().as_u16();
+    let bytes = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    // Prefer the verified auth guard email; fall back to the forum session's username.
+    let identity = found.guard_email.or(found.forum_user);
+
+    let line = match log.format {
+        Format::Combined => format!(
+            "{} - {} [{}] \"{} {} {}\" {} {} \"{}\" \"{}\" \"{}\" {:.1}ms\n",
+            client_ip,
+            identity.as_deref().unwrap_or("-"),
+            time.format("%d/%b/%Y:%H:%M:%S %z"),
+            method,
+            uri,
+            version,
+            status,
+            bytes.as_deref().unwrap_or("-"),
+            quote(&referer),
+            quote(&user_agent),
+            quote(&host),
+            elapsed_ms,
+        ),
+        Format::Json => format!(
+            "{}\n",
+            serde_json::json!({
+                "time": time.to_rfc3339(),
+                "client_ip": client_ip,
+                "identity": identity,
+                "host": host,
+                "method": method,
+                "uri": uri,
+                "protocol": version,
+                "status": status,
+                "bytes": bytes.and_then(|b| b.parse::<u64>().ok()),
+                "duration_ms": elapsed_ms,
+                "referer": referer,
+                "user_agent": user_agent,
+            })
+        ),
+    };
+    let _ = log.lines.send(line);
+    response
+}
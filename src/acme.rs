@@ -3,11 +3,11 @@ use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
-use axum_server::tls_rustls::RustlsConfig;
 use chrono::Utc;
+use base64::{Engine as _, engine::general_purpose};
 use instant_acme::{
-    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, LetsEncrypt,
-    NewAccount, NewOrder, OrderStatus, RetryPolicy,
+    Account, AccountBuilder, AccountCredentials, AuthorizationStatus, ChallengeType,
+    ExternalAccountKey, Identifier, LetsEncrypt, NewAccount, NewOrder, OrderStatus, RetryPolicy,
 };
 use tokio::sync::RwLock;
 use solarized::{
@@ -17,6 +17,7 @@ use solarized::{
     PrintMode::NewLine,
 };
 
+use crate::certs::{cert_groups, CertGroup, CertResolver};
 use crate::config::Config;
 
 /// Maps an ACME HTTP-01 challenge token to the key-authorization string that must
@@ -26,6 +27,12 @@ pub type ChallengeStore = Arc<RwLock<HashMap<String, String>>>;
 
 const ACCOUNT_PATH_DEFAULT: &str = "pems/acme_account.json";
 
+/// Where the ACME account credentials are kept: `acme_account_path`, else
+/// pems/acme_account.json, under `data_dir` when relative.
+pub fn account_path(config: &Config) -> String {
+    crate::paths::config_path(config.acme_account_path.as_deref().unwrap_or(ACCOUNT_PATH_DEFAULT))
+}
+
 pub fn new_store() -> ChallengeStore {
     Arc::new(RwLock::new(HashMap::new()))
 }
@@ -47,7 +54,7 @@ fn log_err(msg: &str) {
 }
 
 /// Read the `notAfter` of the first certificate in a PEM file as a unix timestamp.
-fn parse_not_after(pem_bytes: &[u8]) -> Option<i64> {
+pub(crate) fn parse_not_after(pem_bytes: &[u8]) -> Option<i64> {
     for pem in x509_parser::pem::Pem::iter_from_buffer(pem_bytes) {
         let pem = pem.ok()?;
         if pem.label == "CERTIFICATE" {
@@ -74,8 +81,13 @@ pub async fn cert_needs_renewal(cert_path: &str, renewal_days: u32) -> bool {
     }
 }
 
-fn directory_url(production: bool) -> String {
-    if production {
+/// `acme_directory_url` when set (ZeroSSL, step-ca, Pebble, ...), otherwise Let's
+/// Encrypt production or staging depending on `acme_production`.
+fn directory_url(config: &Config) -> String {
+    if let Some(url) = &config.acme_directory_url {
+        return url.clone();
+    }
+    if config.acme_production {
         LetsEncrypt::Production.url()
     } else {
         LetsEncrypt::Staging.url()
@@ -83,17 +95,41 @@ fn directory_url(production: bool) -> String {
     .to_owned()
 }
 
+/// Account builder whose HTTP client also trusts `acme_root_ca` when set, for
+/// private CAs whose directory is served with a non-public certificate.
+fn account_builder(config: &Config) -> anyhow::Result<AccountBuilder> {
+    Ok(match &config.acme_root_ca {
+        Some(path) => Account::builder_with_root(crate::paths::config_path(path))?,
+        None => Account::builder()?,
+    })
+}
+
+/// External Account Binding from `acme_eab_key_id` / `acme_eab_hmac_key`. CAs hand
+/// out the HMAC key base64url-encoded, padded or not.
+fn external_account_key(config: &Config) -> anyhow::Result<Option<ExternalAccountKey>> {
+    match (&config.acme_eab_key_id, &config.acme_eab_hmac_key) {
+        (Some(key_id), Some(hmac_key)) => {
+            let hmac = general_purpose::URL_SAFE_NO_PAD
+                .decode(hmac_key.trim().trim_end_matches('='))
+                .map_err(|e| anyhow::anyhow!("acme_eab_hmac_key is not valid base64url: {e}"))?;
+            Ok(Some(ExternalAccountKey::new(key_id.clone(), &hmac)))
+        }
+        (None, None) => Ok(None),
+        _ => Err(anyhow::anyhow!(
+            "acme_eab_key_id and acme_eab_hmac_key must be set together"
+        )),
+    }
+}
+
 /// Restore the ACME account from `acme_account_path` if present, otherwise register
 /// a new account and persist its credentials for reuse across restarts.
 async fn load_or_create_account(config: &Config) -> anyhow::Result<Account> {
-    let account_path = config
-        .acme_account_path
-        .as_deref()
-        .unwrap_or(ACCOUNT_PATH_DEFAULT);
+    let account_path = account_path(config);
+    let account_path = account_path.as_str();
 
     if let Ok(bytes) = tokio::fs::read(account_path).await {
         match serde_json::from_slice::<AccountCredentials>(&bytes) {
-            Ok(creds) => match Account::builder()?.from_credentials(creds).await {
+            Ok(creds) => match account_builder(config)?.from_credentials(creds).await {
                 Ok(account) => {
                     log_info("restored existing ACME account from disk");
                     return Ok(account);
@@ -115,15 +151,16 @@ async fn load_or_create_account(config: &Config) -> anyhow::Result<Account> {
         .collect();
     let contact_refs: Vec<&str> = contact.iter().map(|s| s.as_str()).collect();
 
-    let (account, credentials) = Account::builder()?
+    let eab = external_account_key(config)?;
+    let (account, credentials) = account_builder(config)?
         .create(
             &NewAccount {
                 contact: &contact_refs,
                 terms_of_service_agreed: true,
                 only_return_existing: false,
             },
-            directory_url(config.acme_production),
-            None,
+            directory_url(config),
+            eab.as_ref(),
         )
         .await?;
 
@@ -147,38 +184,45 @@ async fn write_file(path: &str, data: &[u8]) -> anyhow::Result<()> {
     Ok(())
 }
 
-/// Ensure a valid certificate exists at the configured paths, obtaining one via
-/// ACME (HTTP-01) when missing or near expiry. Returns `Ok(true)` if a new cert was
-/// written, `Ok(false)` if the existing cert was still fresh (no API call made).
-pub async fn ensure_certificate(config: &Config, store: &ChallengeStore) -> anyhow::Result<bool> {
-    let cert_path = config
-        .ssl_cert_path
-        .as_deref()
-        .ok_or_else(|| anyhow::anyhow!("ssl_cert_path is required when acme_enabled is true"))?;
-    let key_path = config
-        .ssl_key_path
-        .as_deref()
-        .ok_or_else(|| anyhow::anyhow!("ssl_key_path is required when acme_enabled is true"))?;
-    if config.acme_domains.is_empty() {
+/// Ensure a valid certificate exists for one certificate group, obtaining one via
+/// ACME when missing or near expiry. HTTP-01 tokens go into `store`; TLS-ALPN-01
+/// challenge certs go into `resolver`, which the HTTPS listener serves to
+/// `acme-tls/1` handshakes. Returns `Ok(true)` if a new cert was written,
+/// `Ok(false)` if the existing cert was still fresh (no API call made).
+pub async fn ensure_certificate(
+    config: &Config,
+    group: &CertGroup,
+    store: &ChallengeStore,
+    resolver: &CertResolver,
+) -> anyhow::Result<bool> {
+    let cert_path = group.cert_path.as_str();
+    let key_path = group.key_path.as_str();
+    if group.domains.is_empty() {
         return Err(anyhow::anyhow!(
-            "acme_domains must list at least one domain when acme_enabled is true"
+            "{cert_path}: a certificate issued via ACME needs at least one domain"
         ));
     }
 
     if !cert_needs_renewal(cert_path, config.acme_renewal_days).await {
-        log_info("existing certificate is still valid; skipping ACME request");
+        log_info(&format!(
+            "certificate for [{}] is still valid; skipping ACME request",
+            group.domains.join(", "),
+        ));
         return Ok(false);
     }
 
     log_info(&format!(
-        "requesting certificate for [{}] via {}",
-        config.acme_domains.join(", "),
-        if config.acme_production { "production" } else { "staging" },
+        "requesting certificate for [{}] via {} ({})",
+        group.domains.join(", "),
+        directory_url(config),
+        config.acme_challenge,
     ));
+    let tls_alpn = config.acme_challenge == "tls-alpn-01";
+    let challenge_type = if tls_alpn { ChallengeType::TlsAlpn01 } else { ChallengeType::Http01 };
 
     let account = load_or_create_account(config).await?;
-    let identifiers: Vec<Identifier> = config
-        .acme_domains
+    let identifiers: Vec<Identifier> = group
+        .domains
         .iter()
         .map(|d| Identifier::Dns(d.clone()))
         .collect();
@@ -186,68 +230,92 @@ pub async fn ensure_certificate(config: &Config, store: &ChallengeStore) -> anyh
         .new_order(&NewOrder::new(identifiers.as_slice()))
         .await?;
 
-    {
-        let mut authorizations = order.authorizations();
-        while let Some(result) = authorizations.next().await {
-            let mut authz = result?;
-            match authz.status {
-                AuthorizationStatus::Pending => {}
-                AuthorizationStatus::Valid => continue,
-                other => {
-                    return Err(anyhow::anyhow!(
-                        "unexpected authorization status: {other:?}"
-                    ));
+    let result = async {
+        {
+            let mut authorizations = order.authorizations();
+            while let Some(result) = authorizations.next().await {
+                let mut authz = result?;
+                match authz.status {
+                    AuthorizationStatus::Pending => {}
+                    AuthorizationStatus::Valid => continue,
+                    other => {
+                        return Err(anyhow::anyhow!(
+                            "unexpected authorization status: {other:?}"
+                        ));
+                    }
+                }
+                let domain = match authz.identifier().identifier {
+                    Identifier::Dns(domain) => domain.clone(),
+                    other => {
+                        return Err(anyhow::anyhow!("unsupported identifier in authorization: {other:?}"));
+                    }
+                };
+                let mut challenge = authz.challenge(challenge_type.clone()).ok_or_else(|| {
+                    anyhow::anyhow!("the ACME server did not offer a {} challenge for {domain}", config.acme_challenge)
+                })?;
+                if tls_alpn {
+                    resolver.set_challenge(&domain, challenge.key_authorization().digest().as_ref())?;
+                } else {
+                    let token = challenge.token.clone();
+                    let key_auth = challenge.key_authorization().as_str().to_string();
+                    store.write().await.insert(token, key_auth);
                 }
+                challenge.set_ready().await?;
             }
-            let mut challenge = authz.challenge(ChallengeType::Http01).ok_or_else(|| {
-                anyhow::anyhow!("the ACME server did not offer an HTTP-01 challenge")
-            })?;
-            let token = challenge.token.clone();
-            let key_auth = challenge.key_authorization().as_str().to_string();
-            store.write().await.insert(token, key_auth);
-            challenge.set_ready().await?;
         }
-    }
 
-    let status = order.poll_ready(&RetryPolicy::default()).await?;
-    if status != OrderStatus::Ready {
-        store.write().await.clear();
-        return Err(anyhow::anyhow!(
-            "ACME order did not become ready (status: {status:?})"
-        ));
-    }
+        let status = order.poll_ready(&RetryPolicy::default()).await?;
+        if status != OrderStatus::Ready {
+            return Err(anyhow::anyhow!(
+                "ACME order did not become ready (status: {status:?})"
+            ));
+        }
+
+        let key_pem = order.finalize().await?;
+        let chain_pem = order.poll_certificate(&RetryPolicy::default()).await?;
 
-    let key_pem = order.finalize().await?;
-    let chain_pem = order.poll_certificate(&RetryPolicy::default()).await?;
+        write_file(cert_path, chain_pem.as_bytes()).await?;
+        write_file(key_path, key_pem.as_bytes()).await?;
 
-    write_file(cert_path, chain_pem.as_bytes()).await?;
-    write_file(key_path, key_pem.as_bytes()).await?;
+        anyhow::Ok(())
+    }
+    .await;
+    // Challenges only matter while this order is in flight; clear them on
+    // every exit so stale tokens and challenge certs don't linger.
     store.write().await.clear();
+    resolver.clear_challenges();
+    result?;
 
     log_ok(&format!("certificate written to {cert_path} and {key_path}"));
     Ok(true)
 }
 
-/// Periodically renew the certificate and hot-reload it into the running HTTPS
-/// server without a restart.
-pub async fn renewal_loop(config: Arc<Config>, store: ChallengeStore, rustls_config: RustlsConfig) {
-    let cert_path = config.ssl_cert_path.clone();
-    let key_path = config.ssl_key_path.clone();
+/// Check every ACME-managed certificate group now and then every 12 hours,
+/// swapping each renewed cert into the live resolver on its own, without a
+/// restart. The first pass also issues TLS-ALPN-01 certs that could not be
+/// requested before the HTTPS listener was up.
+pub async fn renewal_loop(config: Arc<Config>, store: ChallengeStore, resolver: Arc<CertResolver>) {
+    let groups = cert_groups(&config);
     loop {
-        tokio::time::sleep(Duration::from_secs(12 * 3600)).await;
-        match ensure_certificate(&config, &store).await {
-            Ok(true) => match (cert_path.as_deref(), key_path.as_deref()) {
-                (Some(c), Some(k)) => match crate::utils::build_tls_config(c, k).await {
-                    Ok(tls) => {
-                        rustls_config.reload_from_config(tls);
-                        log_ok("reloaded renewed certificate into the live server");
-                    }
-                    Err(e) => log_err(&format!("failed to reload renewed certificate: {e}")),
+        for (i, group) in groups.iter().enumerate().filter(|(_, g)| g.acme) {
+            match ensure_certificate(&config, group, &store, &resolver).await {
+                Ok(true) => match resolver.load(group, i == 0).await {
+                    Ok(()) => log_ok(&format!(
+                        "reloaded renewed certificate for [{}] into the live server",
+                        group.domains.join(", "),
+                    )),
+                    Err(e) => log_err(&format!(
+                        "failed to reload renewed certificate {}: {e}",
+                        group.cert_path,
+                    )),
                 },
-                _ => {}
-            },
-            Ok(false) => {}
-            Err(e) => log_err(&format!("renewal attempt failed: {e}")),
+                Ok(false) => {}
+                Err(e) => log_err(&format!(
+                    "renewal attempt for [{}] failed: {e}",
+                    group.domains.join(", "),
+                )),
+            }
         }
+        tokio::time::sleep(Duration::from_secs(12 * 3600)).await;
     }
 }
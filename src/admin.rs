@@ -7,6 +7,7 @@ use axum_extra::extract::CookieJar;
 use crate::AppState;
 use crate::auth_guard;
 use crate::config::AdminDashboard;
+use crate::hosts;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -70,10 +71,9 @@ pub struct DeleteFileGuardForm {
 
 // ── Helpers ───────────────────────────────────────────────────────────────────
 
+/// Exact host, then `*.` wildcard, then a dashboard with no domain.
 pub fn find_dashboard_for_host<'a>(dashboards: &'a [AdminDashboard], host: &str) -> Option<&'a AdminDashboard> {
-    dashboards.iter().find(|d| {
-        d.domain.is_empty() || d.domain.eq_ignore_ascii_case(host)
-    })
+    hosts::best(dashboards.iter(), |d| hosts::rank_domain(&d.domain, host))
 }
 
 fn get_hostname(headers: &HeaderMap) -> String {
@@ -181,7 +181,7 @@ pub async fn dashboard_page(
 
     match state.tera.render("admin_dashboard.html", &ctx) {
         Ok(html) => Html(html).into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        Err(e) => state.tera.error_response("admin_dashboard.html", &e),
     }
 }
 
@@ -8,6 +8,7 @@ use axum_extra::extract::cookie::Cookie;
 use crate::AppState;
 use crate::forum::{ForumDb, reqwest_async_http_client};
 use crate::config::AuthGuard;
+use crate::hosts;
 use oauth2::{
     basic::BasicClient, AuthUrl, ClientId, ClientSecret, CsrfToken,
     RedirectUrl, Scope, TokenResponse, TokenUrl, AuthorizationCode,
@@ -33,17 +34,16 @@ pub struct CallbackQuery {
     pub state: Option<String>,
 }
 
-/// Find the first auth guard that matches both the hostname and request path.
-/// A guard with an empty `sites` list applies to all domains.
+/// Find the auth guard for the hostname and request path. Among guards whose
+/// paths match, one listing the exact host wins over a `*.` wildcard, which
+/// wins over a guard with an empty `sites` list (all domains).
 pub fn find_guard<'a>(guards: &'a [AuthGuard], host: &str, path: &str) -> Option<&'a AuthGuard> {
-    guards.iter().find(|g| {
-        let site_match = g.sites.is_empty()
-            || g.sites.iter().any(|s| s.eq_ignore_ascii_case(host));
+    hosts::best(guards.iter(), |g| {
         let path_match = g.paths.iter().any(|p| {
             let prefix = p.trim_end_matches('/');
             path == prefix || path.starts_with(&format!("{}/", prefix))
         });
-        site_match && path_match
+        if path_match { hosts::rank(&g.sites, host) } else { None }
     })
 }
 
@@ -82,7 +82,7 @@ pub async fn load_access_rules(db: &ForumDb) -> Vec<AccessRule> {
 
 pub fn has_db_rule(rules: &[AccessRule], host: &str, path: &str) -> bool {
     rules.iter().any(|rule| {
-        let site_match = rule.domain.is_empty() || rule.domain.eq_ignore_ascii_case(host);
+        let site_match = hosts::rank_domain(&rule.domain, host).is_some();
         let prefix = rule.path.trim_end_matches('/');
         let path_match = path == prefix || path.starts_with(&format!("{}/", prefix));
         site_match && path_match
@@ -93,7 +93,7 @@ pub fn db_rule_allows(rules: &[AccessRule], host: &str, path: &str, email: &str)
     let email_lc = email.to_ascii_lowercase();
     let user_domain = email_lc.split('@').nth(1).unwrap_or("");
     rules.iter().any(|rule| {
-        let site_match = rule.domain.is_empty() || rule.domain.eq_ignore_ascii_case(host);
+        let site_match = hosts::rank_domain(&rule.domain, host).is_some();
         let prefix = rule.path.trim_end_matches('/');
         let path_match = path == prefix || path.starts_with(&format!("{}/", prefix));
         if !site_match || !path_match {
@@ -143,6 +143,7 @@ pub async fn validate_session(db: &ForumDb, token: &str) -> Option<String> {
             .await;
         return None;
     }
+    crate::access_log::note_guard_email(&email);
     Some(email)
 }
 
@@ -201,7 +202,7 @@ pub async fn guard_login(
     // Use a custom login page from the filesystem if configured.
     if configured {
         if let Some(ref path) = state.config.guard_login_page {
-            if let Ok(template) = std::fs::read_to_string(path) {
+            if let Ok(template) = std::fs::read_to_string(crate::paths::config_path(path)) {
                 let body = template
                     .replace("{next}", &urlencode(&next))
                     .replace("{host}", &urlencode(&host));
@@ -0,0 +1,605 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use solarized::{
+    print_fancy,
+    BLUE, CYAN, GREEN, ORANGE, RED,
+    BOLD,
+    PrintMode::NewLine,
+};
+use sqlx::SqlitePool;
+
+use crate::config::Config;
+use crate::AppState;
+
+const MANIFEST_NAME: &str = "manifest.json";
+const MANIFEST_FORMAT: u32 = 1;
+const ZSTD_LEVEL: i32 = 3;
+/// Scheduled backups are named `webify-<UTC timestamp>.tar.zst`; retention
+/// only ever touches files matching this pattern.
+const SCHEDULED_PREFIX: &str = "webify-";
+const SCHEDULED_SUFFIX: &str = ".tar.zst";
+
+/// Lock file the server holds for as long as it runs, so a restore can tell
+/// the database is in use.
+static SERVER_LOCK: OnceLock<File> = OnceLock::new();
+
+fn lock_path() -> PathBuf {
+    let database = &crate::paths::get().database;
+    PathBuf::from(format!("{}.lock", database.display()))
+}
+
+fn open_lock() -> std::io::Result<File> {
+    let path = lock_path();
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    fs::OpenOptions::new().create(true).truncate(false).write(true).open(path)
+}
+
+fn log_info(msg: &str) {
+    print_fancy(&[("[backup] ", BLUE, vec![]), (msg, CYAN, vec![])], NewLine);
+}
+
+fn log_ok(msg: &str) {
+    print_fancy(&[("[backup] ", BLUE, vec![]), (msg, GREEN, vec![BOLD])], NewLine);
+}
+
+fn log_warn(msg: &str) {
+    print_fancy(&[("[backup] ", BLUE, vec![]), (msg, ORANGE, vec![])], NewLine);
+}
+
+fn log_err(msg: &str) {
+    print_fancy(&[("[backup] ", BLUE, vec![]), (msg, RED, vec![BOLD])], NewLine);
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Database,
+    Dir,
+    File,
+}
+
+/// One archived item: where it lives in the archive and where it is restored to.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    kind: Kind,
+    archive: String,
+    path: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format: u32,
+    webify_version: String,
+    created_at: String,
+    schema_version: i64,
+    entries: Vec<Entry>,
+}
+
+/// Archive name for a path on disk. Relative paths are kept; absolute ones
+/// (certificates under /etc, say) are stored under `abs/`.
+fn archive_name(path: &str) -> String {
+    let trimmed = path.trim_start_matches("./");
+    match trimmed.strip_prefix('/') {
+        Some(rest) => format!("abs/{rest}"),
+        None => trimmed.to_string(),
+    }
+}
+
+/// The site's own data under fixed archive names. On restore these go to
+/// wherever this instance's `data_dir` and `[paths]` point, not to the paths
+/// recorded when the backup was taken.
+fn site_locations() -> Vec<(&'static str, Kind, PathBuf)> {
+    let p = crate::paths::get();
+    vec![
+        ("forum.db", Kind::Database, p.database.clone()),
+        ("uploads", Kind::Dir, p.uploads.clone()),
+        ("posts", Kind::Dir, p.posts.clone()),
+        ("drafts", Kind::Dir, p.drafts.clone()),
+        ("blog_images", Kind::Dir, p.blog_images.clone()),
+        ("config.toml", Kind::File, p.config.clone()),
+        ("forum.toml", Kind::File, p.forum_config.clone()),
+    ]
+}
+
+/// Certificates, keys, the ACME account and the client CA that `config` names.
+fn config_files(config: &Config) -> Vec<String> {
+    let mut files = vec![crate::acme::account_path(config)];
+    for group in crate::certs::cert_groups(config) {
+        files.push(group.cert_path);
+        files.push(group.key_path);
+    }
+    if let Some(ca) = &config.client_ca_path {
+        files.push(crate::paths::config_path(ca));
+    }
+    files.sort();
+    files.dedup();
+    files
+}
+
+/// Everything besides the database that belongs in a backup and exists on disk.
+fn collect_entries(config: Option<&Config>) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for (archive, kind, path) in site_locations() {
+        let present = match kind {
+            Kind::Database => false,
+            Kind::Dir => path.is_dir(),
+            Kind::File => path.is_file(),
+        };
+        if present {
+            entries.push(Entry { kind, archive: archive.to_string(), path: path.to_string_lossy().to_string() });
+        }
+    }
+    for file in config.map(config_files).unwrap_or_default() {
+        if Path::new(&file).is_file() {
+            entries.push(Entry { kind: Kind::File, archive: archive_name(&file), path: file });
+        }
+    }
+    entries
+}
+
+fn write_archive(dest: &Path, manifest: &Manifest, snapshot: &Path) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(manifest).map_err(|e| e.to_string())?;
+    // Written beside the destination and renamed into place, so an interrupted
+    // backup never leaves a truncated archive under the real name.
+    let partial = dest.with_extension("partial");
+    let file = File::create(&partial).map_err(|e| format!("{}: {e}", partial.display()))?;
+    let encoder = zstd::Encoder::new(file, ZSTD_LEVEL).map_err(|e| e.to_string())?;
+    let mut tar = tar::Builder::new(encoder);
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    header.set_cksum();
+    tar.append_data(&mut header, MANIFEST_NAME, json.as_slice()).map_err(|e| e.to_string())?;
+
+    for entry in &manifest.entries {
+        let result = match entry.kind {
+            Kind::Database => tar.append_path_with_name(snapshot, &entry.archive),
+            Kind::Dir => tar.append_dir_all(&entry.archive, &entry.path),
+            Kind::File => tar.append_path_with_name(&entry.path, &entry.archive),
+        };
+        result.map_err(|e| format!("{}: {e}", entry.path))?;
+    }
+    let encoder = tar.into_inner().map_err(|e| e.to_string())?;
+    encoder.finish().map_err(|e| e.to_string())?;
+    fs::rename(&partial, dest).map_err(|e| format!("{}: {e}", dest.display()))
+}
+
+/// Write a backup of the running site to `dest`. The database is copied with
+/// `VACUUM INTO`, which reads one consistent snapshot (WAL included) while
+/// other connections keep writing.
+pub async fn create(db: &SqlitePool, config: Option<&Config>, dest: &Path) -> Result<usize, String> {
+    let staging = tempfile::tempdir().map_err(|e| e.to_string())?;
+    let snapshot = staging.path().join("forum.db");
+    sqlx::query("VACUUM INTO ?")
+        .bind(snapshot.to_string_lossy().to_string())
+        .execute(db)
+        .await
+        .map_err(|e| format!("snapshot of {}: {e}", crate::paths::get().database.display()))?;
+    let schema_version = crate::migrations::current_version(db).await?;
+
+    let database = crate::paths::get().database.to_string_lossy().to_string();
+    let mut entries = vec![Entry { kind: Kind::Database, archive: "forum.db".to_string(), path: database }];
+    entries.extend(collect_entries(config));
+    let manifest = Manifest {
+        format: MANIFEST_FORMAT,
+        webify_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: Utc::now().to_rfc3339(),
+        schema_version,
+        entries,
+    };
+    let count = manifest.entries.len();
+    let dest = dest.to_path_buf();
+    tokio::task::spawn_blocking(move || write_archive(&dest, &manifest, &snapshot))
+        .await
+        .map_err(|e| e.to_string())??;
+    drop(staging);
+    Ok(count)
+}
+
+/// Only plain relative components may come out of an archive.
+fn safe_relative(path: &Path) -> bool {
+    path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Take the server's lock on the database for the life of the process.
+/// `webify restore` refuses to run while it is held.
+pub fn hold_server_lock() {
+    let file = match open_lock() {
+        Ok(file) => file,
+        Err(e) => {
+            log_warn(&format!("could not open {}: {e}; restores cannot tell the server is running", lock_path().display()));
+            return;
+        }
+    };
+    match file.try_lock() {
+        Ok(()) => {
+            let _ = SERVER_LOCK.set(file);
+        }
+        Err(fs::TryLockError::WouldBlock) => {
+            log_warn("another process holds the database lock; is a restore or a second server running?");
+        }
+        Err(fs::TryLockError::Error(e)) => {
+            log_warn(&format!("could not lock {}: {e}", lock_path().display()));
+        }
+    }
+}
+
+/// Somewhere on this instance an archive member may be restored to.
+struct Target {
+    archive: String,
+    kind: Kind,
+    path: PathBuf,
+}
+
+/// Every place a restore may write. Destinations come from this instance
+/// only: its data paths for the site's own data, and the files its current
+/// config names for certificates, keys and the ACME account. Paths recorded in
+/// the archive's manifest are never written to.
+fn targets(config: Option<&Config>) -> Vec<Target> {
+    let mut targets: Vec<Target> = site_locations()
+        .into_iter()
+        .map(|(archive, kind, path)| Target { archive: archive.to_string(), kind, path })
+        .collect();
+    for file in config.map(config_files).unwrap_or_default() {
+        targets.push(Target { archive: archive_name(&file), kind: Kind::File, path: PathBuf::from(file) });
+    }
+    targets
+}
+
+/// The target an archive member belongs to: the entry itself, or for a
+/// directory anything beneath it.
+fn target_for(targets: &[Target], member: &Path) -> Option<usize> {
+    targets.iter().position(|t| {
+        member == Path::new(&t.archive) || (t.kind == Kind::Dir && member.starts_with(&t.archive))
+    })
+}
+
+/// Sibling of `path` that a restore stages into, on the same filesystem so it
+/// can be renamed into place.
+fn staging_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!(".{name}.restore"))
+}
+
+/// Where the current copy of `path` waits while a restore swaps it out.
+fn previous_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!(".{name}.previous"))
+}
+
+fn remove_any(path: &Path) {
+    match fs::symlink_metadata(path) {
+        Ok(m) if m.is_dir() => {
+            let _ = fs::remove_dir_all(path);
+        }
+        Ok(_) => {
+            let _ = fs::remove_file(path);
+        }
+        Err(_) => {}
+    }
+}
+
+fn discard_staged(staged: &[Option<PathBuf>]) {
+    for path in staged.iter().flatten() {
+        remove_any(path);
+    }
+}
+
+/// The paths replaced together for a target: the database takes its WAL and
+/// shared-memory files along, which would otherwise be replayed over it.
+fn replaced_paths(target: &Target) -> Vec<PathBuf> {
+    let mut paths = vec![target.path.clone()];
+    if target.kind == Kind::Database {
+        for suffix in ["-wal", "-shm"] {
+            paths.push(PathBuf::from(format!("{}{suffix}", target.path.display())));
+        }
+    }
+    paths
+}
+
+/// Move each current copy aside and its staged replacement into place,
+/// recording both so a failure part way can be undone.
+fn swap_all(
+    targets: &[Target],
+    staged: &[Option<PathBuf>],
+    moved_aside: &mut Vec<(PathBuf, PathBuf)>,
+    placed: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    for (target, staged) in targets.iter().zip(staged) {
+        let Some(staged) = staged else { continue };
+        for path in replaced_paths(target) {
+            if fs::symlink_metadata(&path).is_ok() {
+                let previous = previous_path(&path);
+                remove_any(&previous);
+                fs::rename(&path, &previous).map_err(|e| format!("{}: {e}", path.display()))?;
+                moved_aside.push((path, previous));
+            }
+        }
+        let source = match target.kind {
+            Kind::Dir => staged.join(&target.archive),
+            Kind::Database | Kind::File => staged.clone(),
+        };
+        if !source.exists() {
+            fs::create_dir_all(&source).map_err(|e| format!("{}: {e}", source.display()))?;
+        }
+        fs::rename(&source, &target.path).map_err(|e| format!("{}: {e}", target.path.display()))?;
+        placed.push(target.path.clone());
+    }
+    Ok(())
+}
+
+/// Put everything staged into place, all or nothing: if any rename fails, the
+/// targets already swapped get their previous copies back. A directory
+/// replaces the old one as a whole, so files deleted after the backup was
+/// taken don't survive the restore.
+fn swap_in(targets: &[Target], staged: &[Option<PathBuf>]) -> Result<Vec<PathBuf>, String> {
+    let mut moved_aside = Vec::new();
+    let mut placed = Vec::new();
+    let result = swap_all(targets, staged, &mut moved_aside, &mut placed);
+    if let Err(e) = result {
+        for path in placed.iter().rev() {
+            remove_any(path);
+        }
+        for (path, previous) in moved_aside.iter().rev() {
+            if let Err(undo) = fs::rename(previous, path) {
+                log_err(&format!("could not put back {} from {}: {undo}", path.display(), previous.display()));
+            }
+        }
+        discard_staged(staged);
+        return Err(format!("{e}; nothing was restored"));
+    }
+    for (_, previous) in &moved_aside {
+        remove_any(previous);
+    }
+    discard_staged(staged);
+    Ok(placed)
+}
+
+/// Unpack every member into a staging location next to its target.
+fn stage<R: Read>(
+    members: tar::Entries<'_, R>,
+    targets: &[Target],
+    staged: &mut [Option<PathBuf>],
+) -> Result<(), String> {
+    for member in members {
+        let mut member = member.map_err(|e| e.to_string())?;
+        let name = member.path().map_err(|e| e.to_string())?.into_owned();
+        if !safe_relative(&name) {
+            return Err(format!("refusing unsafe path {} in archive", name.display()));
+        }
+        let Some(i) = target_for(targets, &name) else {
+            log_warn(&format!(
+                "skipping {}: this instance has nowhere to restore it (certificates are restored to the paths config.toml names)",
+                name.display()
+            ));
+            continue;
+        };
+        let target = &targets[i];
+        // Links could point a later member outside its target, so only plain
+        // files and directories are restored.
+        let entry_type = member.header().entry_type();
+        let allowed = entry_type.is_file() || (target.kind == Kind::Dir && entry_type.is_dir());
+        if !allowed {
+            return Err(format!("refusing {} in archive: not a regular file", name.display()));
+        }
+        if let Some(parent) = target.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|e| format!("{}: {e}", parent.display()))?;
+        }
+        let staging = match &staged[i] {
+            Some(path) => path.clone(),
+            None => {
+                let path = staging_path(&target.path);
+                if path.is_dir() {
+                    let _ = fs::remove_dir_all(&path);
+                }
+                if target.kind == Kind::Dir {
+                    fs::create_dir_all(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+                }
+                staged[i] = Some(path.clone());
+                path
+            }
+        };
+        let result = match target.kind {
+            // `unpack_in` keeps the member's relative path and refuses to
+            // follow anything out of the staging directory.
+            Kind::Dir => member.unpack_in(&staging).map(|_| ()),
+            Kind::Database | Kind::File => member.unpack(&staging).map(|_| ()),
+        };
+        result.map_err(|e| format!("{}: {e}", target.path.display()))?;
+    }
+    Ok(())
+}
+
+/// Restore `src` over this instance. Nothing is moved into place until the
+/// whole archive has been unpacked, and a failed swap puts back what it
+/// replaced, so an error leaves the site as it was.
+fn restore_archive(src: &Path, config: Option<&Config>, force: bool) -> Result<(Manifest, Vec<PathBuf>), String> {
+    let file = File::open(src).map_err(|e| format!("{}: {e}", src.display()))?;
+    let decoder = zstd::Decoder::new(file).map_err(|e| e.to_string())?;
+    let mut archive = tar::Archive::new(decoder);
+    let mut members = archive.entries().map_err(|e| e.to_string())?;
+
+    let mut first = members
+        .next()
+        .ok_or("archive is empty")?
+        .map_err(|e| e.to_string())?;
+    if first.path().map_err(|e| e.to_string())?.as_ref() != Path::new(MANIFEST_NAME) {
+        return Err(format!("not a webify backup: {MANIFEST_NAME} is not the first entry"));
+    }
+    let mut json = String::new();
+    first.read_to_string(&mut json).map_err(|e| e.to_string())?;
+    let manifest: Manifest = serde_json::from_str(&json).map_err(|e| format!("{MANIFEST_NAME}: {e}"))?;
+    if manifest.format > MANIFEST_FORMAT {
+        return Err(format!("backup format {} is newer than this webify understands", manifest.format));
+    }
+    if manifest.schema_version > crate::migrations::latest() {
+        return Err(format!(
+            "backup is at schema version {} but this webify only knows up to {}",
+            manifest.schema_version,
+            crate::migrations::latest()
+        ));
+    }
+    // Held until the restore returns, so a server started meanwhile warns.
+    let lock = open_lock().map_err(|e| format!("{}: {e}", lock_path().display()))?;
+    match lock.try_lock() {
+        Ok(()) => {}
+        Err(fs::TryLockError::WouldBlock) => {
+            return Err("the server has the database open; stop it before restoring".to_string());
+        }
+        Err(fs::TryLockError::Error(e)) => return Err(format!("{}: {e}", lock_path().display())),
+    }
+    let database = &crate::paths::get().database;
+    if !force && database.exists() {
+        return Err(format!("{} already exists; pass --force to overwrite it and the site's files", database.display()));
+    }
+
+    let targets = targets(config);
+    let mut staged: Vec<Option<PathBuf>> = targets.iter().map(|_| None).collect();
+    if let Err(e) = stage(members, &targets, &mut staged) {
+        discard_staged(&staged);
+        return Err(e);
+    }
+    let restored = swap_in(&targets, &staged)?;
+    Ok((manifest, restored))
+}
+
+/// Scheduled backups in `dir`, oldest first; the timestamp in the name sorts
+/// chronologically.
+fn scheduled_backups(dir: &Path) -> Vec<PathBuf> {
+    let Ok(read) = fs::read_dir(dir) else { return Vec::new() };
+    let mut backups: Vec<PathBuf> = read
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(SCHEDULED_PREFIX) && n.ends_with(SCHEDULED_SUFFIX))
+        })
+        .collect();
+    backups.sort();
+    backups
+}
+
+/// Drop the oldest scheduled backups in `dir` beyond `keep`.
+fn prune(dir: &Path, keep: usize) {
+    let backups = scheduled_backups(dir);
+    let excess = backups.len().saturating_sub(keep);
+    for old in &backups[..excess] {
+        match fs::remove_file(old) {
+            Ok(()) => log_info(&format!("removed old backup {}", old.display())),
+            Err(e) => log_err(&format!("could not remove {}: {e}", old.display())),
+        }
+    }
+}
+
+/// Time until the next scheduled backup is due: what is left of `interval`
+/// since the newest one in `dir`, or nothing when there is none. Counting from
+/// the last backup rather than from startup means a server restarted more
+/// often than the interval still gets backed up.
+fn first_wait(dir: &Path, interval: Duration) -> Duration {
+    let Some(newest) = scheduled_backups(dir).pop() else { return Duration::ZERO };
+    let age = fs::metadata(&newest)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.elapsed().ok())
+        .unwrap_or(Duration::ZERO);
+    interval.saturating_sub(age)
+}
+
+/// `backup_dir`: write a backup every `backup_interval_hours` and keep the
+/// newest `backup_keep`. Read at startup.
+pub async fn schedule(state: Arc<AppState>) {
+    let Some(dir) = &state.config.backup_dir else { return };
+    let dir = crate::paths::get().in_data_dir(dir);
+    let interval = Duration::from_secs(state.config.backup_interval_hours.max(1) * 3600);
+    let mut wait = first_wait(&dir, interval);
+    log_info(&format!(
+        "scheduled backups to {} every {}h, keeping {}; next in {}m",
+        dir.display(),
+        state.config.backup_interval_hours.max(1),
+        state.config.backup_keep,
+        wait.as_secs() / 60
+    ));
+    loop {
+        tokio::time::sleep(wait).await;
+        wait = interval;
+        if let Err(e) = fs::create_dir_all(&dir) {
+            log_err(&format!("{}: {e}", dir.display()));
+            continue;
+        }
+        let name = format!("{SCHEDULED_PREFIX}{}{SCHEDULED_SUFFIX}", Utc::now().format("%Y%m%d-%H%M%S"));
+        let dest = dir.join(name);
+        match create(&state.forum_db, Some(&state.config), &dest).await {
+            Ok(count) => log_ok(&format!("wrote {} ({count} entries)", dest.display())),
+            Err(e) => log_err(&format!("scheduled backup failed: {e}")),
+        }
+        prune(&dir, state.config.backup_keep.max(1));
+    }
+}
+
+/// `webify backup <file.tar.zst>`
+pub async fn backup_cli(args: &[String]) -> i32 {
+    let Some(dest) = args.first() else {
+        log_err("usage: webify backup <file.tar.zst>");
+        return 2;
+    };
+    let config = crate::config::read_config();
+    if config.is_none() {
+        log_warn("no usable config.toml; certificates and the ACME account are not included");
+    }
+    let db = crate::forum::init_db().await;
+    match create(&db, config.as_ref(), Path::new(dest)).await {
+        Ok(count) => {
+            log_ok(&format!("wrote {dest} ({count} entries)"));
+            0
+        }
+        Err(e) => {
+            log_err(&e);
+            1
+        }
+    }
+}
+
+/// `webify restore <file.tar.zst> [--force]`. Run it with the server stopped.
+pub async fn restore_cli(args: &[String]) -> i32 {
+    let force = args.iter().any(|a| a == "--force");
+    let Some(src) = args.iter().find(|a| !a.starts_with("--")).cloned() else {
+        log_err("usage: webify restore <file.tar.zst> [--force]");
+        return 2;
+    };
+    let config = crate::config::read_config();
+    if config.is_none() {
+        log_warn("no usable config.toml; certificates and the ACME account are not restored");
+    }
+    let result = tokio::task::spawn_blocking(move || restore_archive(Path::new(&src), config.as_ref(), force)).await;
+    match result {
+        Ok(Ok((manifest, restored))) => {
+            for path in &restored {
+                log_info(&format!("restored {}", path.display()));
+            }
+            log_ok(&format!(
+                "restored backup from {} (webify {}, schema version {})",
+                manifest.created_at, manifest.webify_version, manifest.schema_version
+            ));
+            0
+        }
+        Ok(Err(e)) => {
+            log_err(&e);
+            1
+        }
+        Err(e) => {
+            log_err(&e.to_string());
+            1
+        }
+    }
+}
@@ -14,9 +14,17 @@ use chrono::Utc;
 use crate::AppState;
 use crate::auth_guard;
 
-const POSTS_DIR: &str = "static/posts";
-const DRAFTS_DIR: &str = "drafts";
-const IMAGES_DIR: &str = "static/images/blog";
+fn posts_dir() -> &'static str {
+    crate::paths::as_str(&crate::paths::get().posts)
+}
+
+fn drafts_dir() -> &'static str {
+    crate::paths::as_str(&crate::paths::get().drafts)
+}
+
+fn images_dir() -> &'static str {
+    crate::paths::as_str(&crate::paths::get().blog_images)
+}
 
 // ─── Slug / front-matter helpers ─────────────────────────────────────────────
 
@@ -159,6 +167,11 @@ async fn list_dir(dir: &str) -> Vec<PostMeta> {
     out
 }
 
+/// Published posts, newest first.
+pub async fn list_posts() -> Vec<PostMeta> {
+    list_dir(posts_dir()).await
+}
+
 // ─── Auth helpers ────────────────────────────────────────────────────────────
 
 fn host_from_headers(headers: &HeaderMap) -> String {
@@ -200,8 +213,8 @@ pub async fn blog_index(
     headers: HeaderMap,
 ) -> impl IntoResponse {
     let can_edit = viewer_can_edit(&state, &headers).await;
-    let posts = list_dir(POSTS_DIR).await;
-    let drafts = if can_edit { list_dir(DRAFTS_DIR).await } else { Vec::new() };
+    let posts = list_dir(posts_dir()).await;
+    let drafts = if can_edit { list_dir(drafts_dir()).await } else { Vec::new() };
 
     let mut ctx = tera::Context::new();
     ctx.insert("port", &state.config.port);
@@ -212,20 +225,14 @@ pub async fn blog_index(
 
     match state.tera.render("blog.html", &ctx) {
         Ok(html) => Html(html).into_response(),
-        Err(e) => {
-            eprintln!("blog index render error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
-        }
+        Err(e) => state.tera.error_response("blog.html", &e),
     }
 }
 
 fn render_editor(state: &Arc<AppState>, ctx: tera::Context) -> axum::response::Response {
     match state.tera.render("blog-editor.html", &ctx) {
         Ok(html) => Html(html).into_response(),
-        Err(e) => {
-            eprintln!("blog editor render error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
-        }
+        Err(e) => state.tera.error_response("blog-editor.html", &e),
     }
 }
 
@@ -251,9 +258,9 @@ pub async fn edit_post_form(
     AxumPath(slug): AxumPath<String>,
 ) -> impl IntoResponse {
     let slug = sanitize_filename::sanitize(&slug);
-    let (content, status) = match fs::read_to_string(format!("{}/{}.md", POSTS_DIR, slug)).await {
+    let (content, status) = match fs::read_to_string(format!("{}/{}.md", posts_dir(), slug)).await {
         Ok(c) => (c, "published"),
-        Err(_) => match fs::read_to_string(format!("{}/{}.md", DRAFTS_DIR, slug)).await {
+        Err(_) => match fs::read_to_string(format!("{}/{}.md", drafts_dir(), slug)).await {
             Ok(c) => (c, "draft"),
             Err(_) => return (StatusCode::NOT_FOUND, "Post not found").into_response(),
         },
@@ -319,7 +326,7 @@ async fn parse_post_form(mut multipart: Multipart) -> Result<PostForm, String> {
     Ok(form)
 }
 
-/// Save uploaded image bytes to static/images/blog and return its public URL.
+/// Save uploaded image bytes to the blog image directory and return its public URL.
 async fn save_image(slug: &str, bytes: &[u8], original: &str) -> Option<String> {
     let ext: String = Path::new(original)
         .extension()
@@ -327,13 +334,13 @@ async fn save_image(slug: &str, bytes: &[u8], original: &str) -> Option<String>
         .map(|e| e.chars().filter(|c| c.is_ascii_alphanumeric()).collect())
         .filter(|s: &String| !s.is_empty())
         .unwrap_or_else(|| "img".to_string());
-    if fs::create_dir_all(IMAGES_DIR).await.is_err() {
+    if fs::create_dir_all(images_dir()).await.is_err() {
         return None;
     }
     let filename = format!("{}-{}.{}", slug, Uuid::new_v4(), ext.to_ascii_lowercase());
-    let path = format!("{}/{}", IMAGES_DIR, filename);
+    let path = format!("{}/{}", images_dir(), filename);
     fs::write(&path, bytes).await.ok()?;
-    Some(format!("/{}/{}", IMAGES_DIR, filename))
+    Some(format!("{}/{}", crate::paths::get().blog_images_url(), filename))
 }
 
 /// Find a slug not already used by a different file in `dir` (skips `exclude`).
@@ -352,8 +359,8 @@ async fn ensure_unique_slug(base: &str, dir: &str, exclude: &str) -> String {
 /// Map a stored status string to its on-disk directory ("" if unknown).
 fn status_dir(status: &str) -> &'static str {
     match status {
-        "draft" => DRAFTS_DIR,
-        "published" => POSTS_DIR,
+        "draft" => drafts_dir(),
+        "published" => posts_dir(),
         _ => "",
     }
 }
@@ -374,7 +381,7 @@ async fn save_post(form: PostForm) -> axum::response::Response {
         return (StatusCode::BAD_REQUEST, "Title is required").into_response();
     }
     let publish = form.action != "draft";
-    let target_dir = if publish { POSTS_DIR } else { DRAFTS_DIR };
+    let target_dir = if publish { posts_dir() } else { drafts_dir() };
     let is_edit = !form.orig_slug.is_empty();
 
     // Existing front matter (edits only) — source of the stable creation time + image.
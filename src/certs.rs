@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+
+use crate::config::Config;
+
+/// ALPN protocol an ACME server offers when validating TLS-ALPN-01 (RFC 8737).
+pub const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+/// One certificate/key pair and the hostnames it is served for.
+#[derive(Debug, Clone)]
+pub struct CertGroup {
+    /// Where the group comes from in the config, for messages: `ssl_cert_path`
+    /// or `certificate[i]`.
+    pub label: String,
+    pub domains: Vec<String>,
+    pub cert_path: String,
+    pub key_path: String,
+    /// Issue and renew this pair via ACME.
+    pub acme: bool,
+}
+
+/// All certificate groups in the config, with relative cert and key paths
+/// resolved under `data_dir`. The `ssl_cert_path`/`ssl_key_path` pair (for
+/// `acme_domains`) comes first when set; the first group is also the fallback
+/// for clients that send no or an unknown SNI name.
+pub fn cert_groups(config: &Config) -> Vec<CertGroup> {
+    use crate::paths::config_path;
+    let mut groups = Vec::new();
+    if let (Some(cert), Some(key)) = (&config.ssl_cert_path, &config.ssl_key_path) {
+        groups.push(CertGroup {
+            label: "ssl_cert_path".to_string(),
+            domains: config.acme_domains.clone(),
+            cert_path: config_path(cert),
+            key_path: config_path(key),
+            acme: config.acme_enabled,
+        });
+    }
+    for (i, c) in config.certificates.iter().enumerate() {
+        groups.push(CertGroup {
+            label: format!("certificate[{i}]"),
+            domains: c.domains.clone(),
+            cert_path: config_path(&c.cert_path),
+            key_path: config_path(&c.key_path),
+            acme: c.acme.unwrap_or(config.acme_enabled),
+        });
+    }
+    groups
+}
+
+async fn load_certified_key(cert_path: &str, key_path: &str) -> anyhow::Result<CertifiedKey> {
+    let cert_pem = tokio::fs::read(cert_path).await?;
+    let key_pem = tokio::fs::read(key_path).await?;
+
+    let certs: Vec<CertificateDer<'static>> = rustls_pemfile::certs(&mut cert_pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(anyhow::anyhow!("no certificates found in {cert_path}"));
+    }
+
+    let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut key_pem.as_slice())?
+        .ok_or_else(|| anyhow::anyhow!("no private key found in {key_path}"))?;
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(&key)?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+/// Picks the certificate by SNI name: exact host first, then a `*.parent`
+/// wildcard entry, then the default, with the same one-label wildcards as
+/// `hosts`. Entries can be swapped while the server
+/// runs, so each group renews and reloads independently. `acme-tls/1`
+/// handshakes only ever get the pending TLS-ALPN-01 challenge cert.
+#[derive(Debug, Default)]
+pub struct CertResolver {
+    default: RwLock<Option<Arc<CertifiedKey>>>,
+    by_host: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+    challenges: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl CertResolver {
+    /// Read a group's cert/key from disk and start serving it for its domains
+    /// (and for unmatched names when `is_default`).
+    pub async fn load(&self, group: &CertGroup, is_default: bool) -> anyhow::Result<()> {
+        let key = Arc::new(load_certified_key(&group.cert_path, &group.key_path).await?);
+        {
+            let mut hosts = self.by_host.write().unwrap();
+            for domain in &group.domains {
+                hosts.insert(domain.to_ascii_lowercase(), key.clone());
+            }
+        }
+        if is_default {
+            *self.default.write().unwrap() = Some(key);
+        }
+        Ok(())
+    }
+
+    /// Serve a self-signed TLS-ALPN-01 challenge cert for `domain`, carrying the
+    /// SHA-256 digest of the key authorization in the acmeIdentifier extension.
+    pub fn set_challenge(&self, domain: &str, key_authorization_digest: &[u8]) -> anyhow::Result<()> {
+        let key_pair = rcgen::KeyPair::generate()?;
+        let mut params = rcgen::CertificateParams::new(vec![domain.to_string()])?;
+        params.custom_extensions = vec![rcgen::CustomExtension::new_acme_identifier(key_authorization_digest)];
+        let cert = params.self_signed(&key_pair)?;
+        let key = PrivateKeyDer::Pkcs8(key_pair.serialize_der().into());
+        let signing_key = rustls::crypto::ring::sign::any_supported_type(&key)?;
+        self.challenges.write().unwrap().insert(
+            domain.to_ascii_lowercase(),
+            Arc::new(CertifiedKey::new(vec![cert.der().clone()], signing_key)),
+        );
+        Ok(())
+    }
+
+    pub fn clear_challenges(&self) {
+        self.challenges.write().unwrap().clear();
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let acme_tls = client_hello
+            .alpn()
+            .is_some_and(|mut protocols| protocols.any(|p| p == ACME_TLS_ALPN));
+        if acme_tls {
+            let name = client_hello.server_name()?.to_ascii_lowercase();
+            return self.challenges.read().unwrap().get(&name).cloned();
+        }
+        if let Some(name) = client_hello.server_name() {
+            let name = name.to_ascii_lowercase();
+            let hosts = self.by_host.read().unwrap();
+            if let Some(key) = hosts.get(&name) {
+                return Some(key.clone());
+            }
+            if let Some((_, parent)) = name.split_once('.')
+                && let Some(key) = hosts.get(&format!("*.{parent}"))
+            {
+                return Some(key.clone());
+            }
+        }
+        self.default.read().unwrap().clone()
+    }
+}
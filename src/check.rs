@@ -0,0 +1,666 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use tera::Tera;
+use solarized::{
+    print_fancy,
+    BLUE, CYAN, GREEN, ORANGE, RED, VIOLET, YELLOW,
+    BOLD,
+    PrintMode::NewLine,
+};
+
+use crate::certs::cert_groups;
+use crate::config::{parse_config, Config};
+use crate::constants::EMBEDDED_TEMPLATES;
+use crate::hosts;
+use crate::ip_filter::IpNet;
+use crate::listeners::Address;
+use crate::site_headers::HeaderPolicy;
+
+/// GET routes every site router registers before the config routes are added.
+/// A config route on one of these paths makes axum panic at startup.
+const BUILTIN_GET_ROUTES: &[&str] = &[
+    "/thumbnail/{*path}",
+    "/blog",
+    "/blog/new",
+    "/blog/edit/{slug}",
+    "/blog/{post_name}",
+    "/favicon.ico",
+    "/auth/login",
+    "/auth/google",
+    "/auth/callback",
+    "/auth/logout",
+    "/auth/file-gate",
+    "/interaction",
+    "/interaction/list/{role}",
+    "/ws/interaction/{room_id}",
+];
+
+/// Directories every site router mounts with `nest_service`.
+const BUILTIN_MOUNTS: &[&str] = &[
+    "/static", "/templates", "/uploads", "/css", "/styles", "/js", "/scripts", "/images",
+];
+
+/// Templates rendered by built-in routes that are always mounted.
+const BUILTIN_TEMPLATES: &[&str] = &[
+    "blog.html",
+    "blog-editor.html",
+    "interaction.html",
+    "stream.html",
+    "view.html",
+];
+
+const FORUM_TEMPLATES: &[&str] = &[
+    "forum_index.html",
+    "forum_category.html",
+    "forum_new_topic.html",
+    "topic.html",
+    "forum_edit_post.html",
+    "forum_admin.html",
+];
+
+enum Level {
+    Error,
+    Warning,
+}
+
+struct Finding {
+    level: Level,
+    subject: String,
+    message: String,
+}
+
+#[derive(Default)]
+struct Report {
+    findings: Vec<Finding>,
+}
+
+impl Report {
+    fn error(&mut self, subject: impl Into<String>, message: impl Into<String>) {
+        self.findings.push(Finding { level: Level::Error, subject: subject.into(), message: message.into() });
+    }
+
+    fn warn(&mut self, subject: impl Into<String>, message: impl Into<String>) {
+        self.findings.push(Finding { level: Level::Warning, subject: subject.into(), message: message.into() });
+    }
+
+    fn count(&self, error: bool) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| matches!(f.level, Level::Error) == error)
+            .count()
+    }
+
+    fn print(&self) {
+        for f in &self.findings {
+            let (label, color) = match f.level {
+                Level::Error => ("error   ", RED),
+                Level::Warning => ("warning ", YELLOW),
+            };
+            print_fancy(&[
+                (label, color, vec![BOLD]),
+                (&f.subject, VIOLET, vec![]),
+                (": ", CYAN, vec![]),
+                (&f.message, CYAN, vec![]),
+            ], NewLine);
+        }
+    }
+}
+
+fn route_subject(domain: &str, path: &str) -> String {
+    format!("routes.\"{domain}\".\"{path}\"")
+}
+
+fn normalize(path: &str) -> String {
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.is_empty() { "/".to_string() } else { trimmed.to_string() }
+}
+
+fn prefix_covers(prefix: &str, path: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path == prefix || path.starts_with(&format!("{}/", prefix))
+}
+
+fn sites_overlap(a: &[String], b: &str) -> bool {
+    a.is_empty() || b.is_empty() || a.iter().any(|s| hosts::matches(s, b) || hosts::matches(b, s))
+}
+
+/// Host patterns are an exact name or a single leading `*.` label.
+fn check_host_pattern(report: &mut Report, subject: &str, host: &str) {
+    let rest = host.strip_prefix("*.").unwrap_or(host);
+    if rest.is_empty() || rest.contains('*') {
+        report.error(subject, format!(
+            "\"{host}\" is not a valid host; use an exact name or a leading \"*.\" wildcard such as \"*.example.com\""
+        ));
+    }
+}
+
+fn check_hosts(report: &mut Report, config: &Config) {
+    let keyed = [
+        ("routes", config.sites.keys().collect::<Vec<_>>()),
+        ("whitelist", config.whitelists.keys().collect()),
+        ("blacklist", config.blacklists.keys().collect()),
+        ("compression", config.compression.keys().collect()),
+        ("headers", config.headers.keys().collect()),
+    ];
+    for (section, keys) in keyed {
+        for host in keys.into_iter().filter(|h| h.as_str() != "default") {
+            check_host_pattern(report, &format!("{section}.\"{host}\""), host);
+        }
+    }
+    for (i, guard) in config.auth_guards.iter().enumerate() {
+        for host in &guard.sites {
+            check_host_pattern(report, &format!("auth_guard[{i}]"), host);
+        }
+    }
+    for (i, guard) in config.client_cert_guards.iter().enumerate() {
+        for host in &guard.sites {
+            check_host_pattern(report, &format!("client_cert_guard[{i}]"), host);
+        }
+    }
+    for (i, rule) in config.rate_limits.iter().enumerate() {
+        for host in &rule.sites {
+            check_host_pattern(report, &format!("rate_limit[{i}]"), host);
+        }
+    }
+    for (i, dashboard) in config.admin_dashboards.iter().enumerate() {
+        if !dashboard.domain.is_empty() {
+            check_host_pattern(report, &format!("admin_dashboard[{i}]"), &dashboard.domain);
+        }
+    }
+}
+
+fn check_template(report: &mut Report, tera: Option<&Tera>, subject: &str, file_path: &str) {
+    let name = file_path.trim_start_matches("static/");
+    let found = match tera {
+        Some(t) => t.get_template_names().any(|n| n == name),
+        None => crate::paths::get().static_dir.join(name).is_file() || EMBEDDED_TEMPLATES.iter().any(|(n, _)| *n == name),
+    };
+    if !found {
+        report.error(subject, format!("template {name} is not built in or under static/ (requests will return 500)"));
+    }
+}
+
+fn check_dir(report: &mut Report, subject: &str, dir: &str) {
+    if !Path::new(&crate::paths::config_path(dir)).is_dir() {
+        report.error(subject, format!("directory {dir} does not exist"));
+    }
+}
+
+/// Route problems that make axum panic while the site routers are built, as
+/// `(subject, message)`. Hot reload runs these too, so a config that would
+/// panic is rejected instead of taking the reload task down.
+pub fn route_conflicts(config: &Config) -> Vec<(String, String)> {
+    let mut conflicts = Vec::new();
+    for (domain, routes) in &config.sites {
+        let mut seen: HashMap<String, &str> = HashMap::new();
+        for (path, _) in routes {
+            let subject = route_subject(domain, path);
+            if !path.starts_with('/') {
+                conflicts.push((subject.clone(), "route paths must start with '/'".to_string()));
+            }
+            if let Some(first) = seen.insert(normalize(path), path) {
+                conflicts.push((subject.clone(), format!("duplicates route \"{first}\" on the same host")));
+            }
+            if BUILTIN_GET_ROUTES.contains(&normalize(path).as_str()) {
+                conflicts.push((subject.clone(), "overlaps a built-in GET route; the server will refuse to start".to_string()));
+            }
+            if BUILTIN_MOUNTS.contains(&normalize(path).as_str()) {
+                conflicts.push((subject, "overlaps a built-in static directory mount".to_string()));
+            }
+        }
+    }
+    conflicts
+}
+
+fn check_routes(report: &mut Report, config: &Config, tera: Option<&Tera>) {
+    for (subject, message) in route_conflicts(config) {
+        report.error(subject, message);
+    }
+    for (domain, routes) in &config.sites {
+        for (path, settings) in routes {
+            let subject = route_subject(domain, path);
+            match settings.as_slice() {
+                [_, mode] if mode == "forum" => {
+                    for name in FORUM_TEMPLATES {
+                        check_template(report, tera, &subject, name);
+                    }
+                }
+                [settings_type, slides_dir] if settings_type == "slideshow" => {
+                    check_dir(report, &subject, slides_dir);
+                }
+                [dir_path, mode] if mode == "static" => {
+                    check_dir(report, &subject, dir_path);
+                }
+                [file_path, watch_file, mode] if mode == "live" => {
+                    check_template(report, tera, &subject, file_path);
+                    if !Path::new(&crate::paths::config_path(watch_file)).is_file() {
+                        report.warn(&subject, format!("watched file {watch_file} does not exist yet"));
+                    }
+                }
+                [_, fpm_addr, mode] if mode == "php" => {
+                    if fpm_addr.parse::<SocketAddr>().is_err() {
+                        report.error(&subject, format!("PHP-FPM address {fpm_addr} is not a host:port socket address"));
+                    }
+                }
+                [upstream, mode] if mode == "proxy" => {
+                    if let Err(e) = crate::proxy::Proxy::new(upstream, path) {
+                        report.error(&subject, e);
+                    }
+                }
+                [_, mode] if mode == "live" || mode == "php" => {
+                    report.error(&subject, format!(
+                        "\"{mode}\" mode takes three values; this is being treated as a media gallery of directory \"{mode}\""
+                    ));
+                }
+                [file_path, media_dir, rest @ ..] => {
+                    check_template(report, tera, &subject, file_path);
+                    check_dir(report, &subject, media_dir);
+                    match rest {
+                        [] => {}
+                        [sort] if sort == "random" || sort == "alphanumeric" => {}
+                        [sort] => report.warn(&subject, format!(
+                            "unknown sort \"{sort}\" (expected \"random\" or \"alphanumeric\"); sorting alphanumerically"
+                        )),
+                        _ => report.warn(&subject, format!(
+                            "gallery routes take at most three values; ignoring {:?}", &rest[1..]
+                        )),
+                    }
+                }
+                [file_path] => {
+                    check_template(report, tera, &subject, file_path);
+                }
+                [] => {
+                    report.error(&subject, "route has no settings and is ignored");
+                }
+            }
+        }
+    }
+    for host in config.compression.keys() {
+        if host != "default" && !config.sites.keys().any(|site| hosts::matches(host, site)) {
+            report.warn(format!("compression.\"{host}\""), "no routes are configured for this host; the flag has no effect");
+        }
+    }
+    for (host, site_headers) in &config.headers {
+        let subject = format!("headers.\"{host}\"");
+        if host != "default" && !config.sites.keys().any(|site| hosts::matches(host, site)) {
+            report.warn(&subject, "no routes are configured for this host; the headers are never sent");
+        }
+        for e in HeaderPolicy::build(site_headers).1 {
+            report.error(&subject, e);
+        }
+        if site_headers.hsts.is_some() && !config.ssl_enabled {
+            report.warn(&subject, "hsts is only sent over HTTPS and ssl_enabled is false");
+        }
+    }
+}
+
+fn check_tls(report: &mut Report, config: &Config) {
+    let http_modes = ["serve", "redirect", "https_only"];
+    if !http_modes.contains(&config.http_mode.as_str()) {
+        report.error("http_mode", format!(
+            "unknown mode \"{}\" (expected serve, redirect or https_only); falling back to serve",
+            config.http_mode
+        ));
+    } else if config.http_mode != "serve" && !config.ssl_enabled {
+        report.warn("http_mode", "has no effect unless ssl_enabled is true");
+    }
+    if !["localhost", "local", "lan", "public", "production", "prod"].contains(&config.scope.trim().to_lowercase().as_str()) {
+        report.warn("scope", format!("unknown scope \"{}\"; binding to 127.0.0.1", config.scope));
+    }
+    if !["combined", "json"].contains(&config.access_log_format.as_str()) {
+        report.error("access_log_format", format!(
+            "unknown format \"{}\" (expected combined or json); using combined",
+            config.access_log_format
+        ));
+    }
+    if !["never", "daily", "size"].contains(&config.access_log_rotate.as_str()) {
+        report.error("access_log_rotate", format!(
+            "unknown rotation \"{}\" (expected never, daily or size); never rotating",
+            config.access_log_rotate
+        ));
+    }
+    if config.backup_dir.is_some() {
+        if config.backup_interval_hours == 0 {
+            report.error("backup_interval_hours", "must be at least 1; backing up hourly");
+        }
+        if config.backup_keep == 0 {
+            report.error("backup_keep", "must be at least 1; keeping the newest backup only");
+        }
+    }
+    let mut listen_addresses = HashSet::new();
+    for (i, listener) in config.listeners.iter().enumerate() {
+        let subject = format!("listener[{i}]");
+        match listener.address.parse::<Address>() {
+            Ok(address) => {
+                if !listen_addresses.insert(address.clone()) {
+                    report.error(&subject, format!("\"{}\" is listed twice", listener.address));
+                }
+                if listener.tls && matches!(address, Address::Unix(_)) {
+                    report.error(&subject, "tls is only supported on TCP sockets");
+                }
+                if listener.proxy_protocol && matches!(address, Address::Unix(_)) {
+                    report.error(&subject, "proxy_protocol is only supported on TCP sockets");
+                }
+            }
+            Err(e) => report.error(&subject, e),
+        }
+        if listener.tls && !config.ssl_enabled {
+            report.error(&subject, "tls = true needs ssl_enabled and a certificate");
+        }
+    }
+    if config.listeners.iter().any(|l| l.proxy_protocol) && config.trusted_proxies.is_empty() {
+        report.warn(
+            "listener",
+            "proxy_protocol is on but trusted_proxies is empty; anyone who can reach the listener can claim any client IP",
+        );
+    }
+    if !config.listeners.is_empty() && config.ssl_enabled && !config.listeners.iter().any(|l| l.tls) {
+        report.warn("listener", "ssl_enabled is true but no listener has tls = true; HTTPS is not served");
+    }
+    if config.ssl_enabled {
+        if config.listeners.is_empty() && config.port == config.ssl_port {
+            report.error("ssl_port", "must differ from port when ssl_enabled is true");
+        }
+        if config.ssl_cert_path.is_some() != config.ssl_key_path.is_some() {
+            report.error("ssl_enabled", "ssl_cert_path and ssl_key_path must be set together");
+        }
+        if cert_groups(config).is_empty() {
+            report.error("ssl_enabled", "requires ssl_cert_path/ssl_key_path or at least one [[certificate]]");
+        }
+    }
+    let mut seen_domains: HashSet<String> = HashSet::new();
+    for group in cert_groups(config) {
+        let subject = &group.label;
+        // With ACME the files are created on first start.
+        if !group.acme {
+            if !Path::new(&group.cert_path).is_file() {
+                report.error(subject, format!("{} does not exist", group.cert_path));
+            }
+            if !Path::new(&group.key_path).is_file() {
+                report.error(subject, format!("{} does not exist", group.key_path));
+            }
+        } else if group.domains.is_empty() {
+            report.error(subject, "ACME needs at least one domain to request the certificate for");
+        }
+        for domain in &group.domains {
+            if group.acme && domain.starts_with("*.") {
+                report.error(subject, format!("{domain}: wildcard certificates need the DNS-01 challenge, which webify doesn't answer"));
+            }
+            if !seen_domains.insert(domain.to_ascii_lowercase()) {
+                report.warn(subject, format!("{domain} is also listed by an earlier certificate; this one wins for SNI"));
+            }
+        }
+    }
+    if config.acme_enabled || config.certificates.iter().any(|c| c.acme == Some(true)) {
+        if !config.ssl_enabled {
+            report.error("acme_enabled", "requires ssl_enabled = true");
+        }
+        if !["http-01", "tls-alpn-01"].contains(&config.acme_challenge.as_str()) {
+            report.error("acme_challenge", format!(
+                "unknown challenge \"{}\" (expected http-01 or tls-alpn-01)",
+                config.acme_challenge
+            ));
+        }
+        if config.acme_contact_email.is_none() {
+            report.warn("acme_contact_email", "not set; the CA can't warn you about expiring certificates");
+        }
+        if let Some(url) = &config.acme_directory_url
+            && !url.starts_with("https://")
+        {
+            report.error("acme_directory_url", "must be an https:// URL");
+        }
+        if config.acme_eab_key_id.is_some() != config.acme_eab_hmac_key.is_some() {
+            report.error("acme_eab_key_id", "acme_eab_key_id and acme_eab_hmac_key must be set together");
+        }
+        if let Some(root) = &config.acme_root_ca
+            && !Path::new(&crate::paths::config_path(root)).is_file()
+        {
+            report.error("acme_root_ca", format!("{root} does not exist"));
+        }
+        if !config.acme_production && config.acme_directory_url.is_none() {
+            report.warn("acme_production", "false: certificates come from the staging CA and won't be trusted by browsers");
+        }
+    }
+}
+
+fn check_access(report: &mut Report, config: &Config, tera: Option<&Tera>) {
+    for (section, lists) in [("whitelist", &config.whitelists), ("blacklist", &config.blacklists)] {
+        for (host, entries) in lists {
+            for entry in entries {
+                if let Err(e) = entry.parse::<IpNet>() {
+                    report.error(format!("{section}.\"{host}\""), e);
+                }
+            }
+        }
+    }
+    for entry in &config.trusted_proxies {
+        if let Err(e) = entry.parse::<IpNet>() {
+            report.error("trusted_proxies", e);
+        }
+    }
+
+    for (i, rule) in config.rate_limits.iter().enumerate() {
+        let subject = format!("rate_limit[{i}]");
+        if rule.paths.is_empty() {
+            report.warn(&subject, "has no paths and limits nothing");
+        }
+        for p in &rule.paths {
+            if !p.starts_with('/') {
+                report.error(&subject, format!("path \"{p}\" must start with '/'"));
+            }
+        }
+        if rule.requests == 0 || rule.per_seconds == 0 {
+            report.error(&subject, "requests and per_seconds must both be at least 1");
+        }
+        if rule.by != "ip" && rule.by != "user" {
+            report.error(&subject, format!("unknown key \"{}\" (expected \"ip\" or \"user\")", rule.by));
+        }
+    }
+
+    let oauth_ready = config.google_client_id.is_some()
+        && config.google_client_secret.is_some()
+        && config.guard_redirect_url.is_some();
+    for (i, guard) in config.auth_guards.iter().enumerate() {
+        let subject = format!("auth_guard[{i}]");
+        if guard.paths.is_empty() {
+            report.warn(&subject, "has no paths and protects nothing");
+        }
+        for p in &guard.paths {
+            if !p.starts_with('/') {
+                report.error(&subject, format!("path \"{p}\" must start with '/'"));
+            }
+        }
+        if guard.allowed_emails.is_empty() && guard.allowed_domains.is_empty() {
+            report.warn(&subject, "allows no emails or domains; only DB access rules can open it");
+        }
+        if !oauth_ready {
+            report.error(&subject, "needs google_client_id, google_client_secret and guard_redirect_url");
+        }
+    }
+
+    if let Some(path) = &config.client_ca_path {
+        if !config.ssl_enabled {
+            report.warn("client_ca_path", "is only used by HTTPS listeners; ssl_enabled is false");
+        }
+        if let Err(e) = crate::client_cert::client_verifier(&crate::paths::config_path(path)) {
+            report.error("client_ca_path", e);
+        }
+    }
+    for (i, guard) in config.client_cert_guards.iter().enumerate() {
+        let subject = format!("client_cert_guard[{i}]");
+        if guard.paths.is_empty() {
+            report.warn(&subject, "has no paths and protects nothing");
+        }
+        for p in &guard.paths {
+            if !p.starts_with('/') {
+                report.error(&subject, format!("path \"{p}\" must start with '/'"));
+            }
+        }
+        if guard.allowed_names.is_empty() && guard.allowed_emails.is_empty() && guard.allowed_domains.is_empty() {
+            report.warn(&subject, "allows no names, emails or domains; every request is refused");
+        }
+        if config.client_ca_path.is_none() || !config.ssl_enabled {
+            report.error(&subject, "needs ssl_enabled and client_ca_path; every request is refused");
+        }
+    }
+
+    for (i, fg) in config.file_guards.iter().enumerate() {
+        let subject = format!("file_guard[{i}]");
+        if fg.hash.len() != 64 || !fg.hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            report.error(&subject, "hash must be a 64-character hex SHA-256 digest");
+        } else if fg.hash.chars().any(|c| c.is_ascii_uppercase()) {
+            report.error(&subject, "hash must be lowercase hex");
+        }
+    }
+    if !config.file_guards.is_empty() {
+        check_template(report, tera, "file_guard", "file-gate.html");
+    }
+
+    for (i, dashboard) in config.admin_dashboards.iter().enumerate() {
+        let subject = format!("admin_dashboard[{i}]");
+        let dp = dashboard.path.trim_end_matches('/');
+        if !dashboard.path.starts_with('/') {
+            report.error(&subject, "path must start with '/'");
+        }
+        if dashboard.owners.is_empty() {
+            report.warn(&subject, "has no owners; nobody can grant editor access");
+        }
+        for earlier in &config.admin_dashboards[..i] {
+            if earlier.domain.eq_ignore_ascii_case(&dashboard.domain) {
+                report.warn(&subject, "another dashboard already covers this domain; only the first is used");
+            }
+        }
+        for (domain, routes) in &config.sites {
+            let site_match = hosts::rank_domain(&dashboard.domain, domain).is_some()
+                || hosts::matches(domain, &dashboard.domain);
+            if site_match && routes.iter().any(|(p, _)| p.trim_end_matches('/') == dp) {
+                report.error(&subject, format!(
+                    "path {} is also a route on \"{domain}\"; the dashboard will not be mounted there",
+                    dashboard.path
+                ));
+            }
+        }
+        for (j, guard) in config.auth_guards.iter().enumerate() {
+            if !sites_overlap(&guard.sites, &dashboard.domain) {
+                continue;
+            }
+            for p in &guard.paths {
+                if prefix_covers(p, dp) || prefix_covers(dp, p) {
+                    report.warn(&subject, format!(
+                        "overlaps auth_guard[{j}] path {p}; under the dashboard path only owners and editors are checked"
+                    ));
+                }
+            }
+        }
+    }
+    if !config.admin_dashboards.is_empty() {
+        check_template(report, tera, "admin_dashboard", "admin_dashboard.html");
+    }
+    if config.metrics {
+        for domain in config.sites.keys() {
+            let whitelisted = hosts::lookup(&config.whitelists, domain).is_some_and(|list| !list.is_empty());
+            let guarded = crate::auth_guard::find_guard(&config.auth_guards, domain, "/metrics").is_some()
+                || crate::client_cert::find_guard(&config.client_cert_guards, domain, "/metrics").is_some();
+            if !whitelisted && !guarded {
+                report.warn("metrics", format!(
+                    "/metrics on \"{domain}\" is public; add a [whitelist] or an auth_guard covering it"
+                ));
+            }
+        }
+    }
+}
+
+/// `webify check`: validate config.toml, forum.toml and the templates and
+/// directories they reference without starting the server. Returns the process
+/// exit code (non-zero when any error was found).
+pub fn run() -> i32 {
+    let mut report = Report::default();
+    // Paths are resolved here rather than by main, so a config that doesn't
+    // parse is reported like any other error.
+    let paths = match crate::paths::init() {
+        Ok(paths) => paths,
+        Err(e) => {
+            print_fancy(&[
+                ("error   ", RED, vec![BOLD]),
+                (&crate::paths::config_file().display().to_string(), VIOLET, vec![]),
+                (&format!(": {e}"), CYAN, vec![]),
+            ], NewLine);
+            return 1;
+        }
+    };
+    let config_name = paths.config.display().to_string();
+    let contents = match std::fs::read_to_string(&paths.config) {
+        Ok(c) => c,
+        Err(e) => {
+            print_fancy(&[
+                ("error   ", RED, vec![BOLD]),
+                (&config_name, VIOLET, vec![]),
+                (&format!(": {e}"), CYAN, vec![]),
+            ], NewLine);
+            return 1;
+        }
+    };
+    let config = match parse_config(&contents) {
+        Ok(c) => c,
+        Err(e) => {
+            print_fancy(&[
+                ("error   ", RED, vec![BOLD]),
+                (&config_name, VIOLET, vec![]),
+                (&format!(": {e}"), CYAN, vec![]),
+            ], NewLine);
+            return 1;
+        }
+    };
+    if let Err(e) = crate::forum::load_forum_config() {
+        report.error("forum.toml", e.to_string());
+    }
+
+    let tera = match crate::utils::load_templates() {
+        Ok(t) => Some(t),
+        Err(e) => {
+            let mut msg = e.to_string();
+            let mut source = std::error::Error::source(&e);
+            while let Some(s) = source {
+                msg.push_str(&format!(": {s}"));
+                source = s.source();
+            }
+            report.error("static/**/*.html", msg);
+            None
+        }
+    };
+    for name in BUILTIN_TEMPLATES {
+        let name_found = match &tera {
+            Some(t) => t.get_template_names().any(|n| n == *name),
+            None => paths.static_dir.join(name).is_file() || EMBEDDED_TEMPLATES.iter().any(|(n, _)| n == name),
+        };
+        if !name_found {
+            report.warn("static", format!("{name} is not built in or under static/; its route will return 500"));
+        }
+    }
+
+    if !paths.data_dir.as_os_str().is_empty() && !paths.data_dir.is_dir() {
+        report.warn("data_dir", format!("{} does not exist yet", paths.data_dir.display()));
+    }
+    check_routes(&mut report, &config, tera.as_ref());
+    check_tls(&mut report, &config);
+    check_access(&mut report, &config, tera.as_ref());
+    check_hosts(&mut report, &config);
+
+    report.print();
+    let errors = report.count(true);
+    let warnings = report.count(false);
+    if errors == 0 && warnings == 0 {
+        print_fancy(&[(&config_name, VIOLET, vec![]), (" looks good", GREEN, vec![BOLD])], NewLine);
+    } else {
+        print_fancy(&[
+            (&format!("\n{errors}"), if errors > 0 { RED } else { GREEN }, vec![BOLD]),
+            (" error(s), ", CYAN, vec![]),
+            (&format!("{warnings}"), if warnings > 0 { ORANGE } else { GREEN }, vec![BOLD]),
+            (" warning(s) in ", CYAN, vec![]),
+            (&config_name, BLUE, vec![]),
+        ], NewLine);
+    }
+    if errors > 0 { 1 } else { 0 }
+}
@@ -0,0 +1,144 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use axum::{middleware::AddExtension, Extension};
+use axum_server::accept::Accept;
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::WebPkiClientVerifier;
+use rustls::RootCertStore;
+use tokio_rustls::server::TlsStream;
+use tower::Layer;
+use x509_parser::extensions::GeneralName;
+
+use crate::config::ClientCertGuard;
+use crate::hosts;
+
+/// Names taken from a client certificate that passed verification against
+/// `client_ca_path`.
+#[derive(Debug, Clone, Default)]
+pub struct ClientIdentity {
+    pub common_names: Vec<String>,
+    /// SAN rfc822Name entries, the subject emailAddress, and a CN that looks
+    /// like an address.
+    pub emails: Vec<String>,
+}
+
+/// Request extension set on every HTTPS connection; `None` when the client
+/// sent no certificate.
+#[derive(Debug, Clone, Default)]
+pub struct PeerCert(pub Option<Arc<ClientIdentity>>);
+
+/// Verifier trusting the CAs in a PEM bundle. Clients without a certificate
+/// still complete the handshake so unguarded paths keep working; a
+/// certificate that doesn't chain to the bundle fails it.
+pub fn client_verifier(ca_path: &str) -> Result<Arc<dyn ClientCertVerifier>, String> {
+    let pem = std::fs::read(ca_path).map_err(|e| format!("{ca_path}: {e}"))?;
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+        let cert = cert.map_err(|e| format!("{ca_path}: {e}"))?;
+        roots.add(cert).map_err(|e| format!("{ca_path}: {e}"))?;
+    }
+    if roots.is_empty() {
+        return Err(format!("{ca_path}: no certificates found"));
+    }
+    WebPkiClientVerifier::builder(Arc::new(roots))
+        .allow_unauthenticated()
+        .build()
+        .map_err(|e| format!("{ca_path}: {e}"))
+}
+
+fn identity(der: &[u8]) -> Option<ClientIdentity> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    let common_names: Vec<String> = cert
+        .subject()
+        .iter_common_name()
+        .filter_map(|cn| cn.as_str().ok())
+        .map(str::to_string)
+        .collect();
+    let mut emails: Vec<String> = cert
+        .subject()
+        .iter_email()
+        .filter_map(|e| e.as_str().ok())
+        .map(str::to_string)
+        .collect();
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            if let GeneralName::RFC822Name(email) = name {
+                emails.push(email.to_string());
+            }
+        }
+    }
+    emails.extend(common_names.iter().filter(|cn| cn.contains('@')).cloned());
+    Some(ClientIdentity { common_names, emails })
+}
+
+/// Wraps the rustls acceptor and attaches the verified client certificate, if
+/// any, to every request on the connection as a `PeerCert` extension.
+#[derive(Clone)]
+pub struct ClientCertAcceptor<A> {
+    inner: A,
+}
+
+impl<A> ClientCertAcceptor<A> {
+    pub fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+impl<I, S, A, T> Accept<I, S> for ClientCertAcceptor<A>
+where
+    A: Accept<I, S, Stream = TlsStream<T>>,
+    A::Future: Send + 'static,
+    A::Service: Send + 'static,
+    T: Send + 'static,
+{
+    type Stream = TlsStream<T>;
+    type Service = AddExtension<A::Service, PeerCert>;
+    type Future = Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let accepted = self.inner.accept(stream, service);
+        Box::pin(async move {
+            let (stream, service) = accepted.await?;
+            let cert = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|chain| chain.first())
+                .and_then(|der| identity(der))
+                .map(Arc::new);
+            Ok((stream, Extension(PeerCert(cert)).layer(service)))
+        })
+    }
+}
+
+/// Find the client certificate guard for the hostname and request path, with
+/// the same precedence as `auth_guard::find_guard`.
+pub fn find_guard<'a>(guards: &'a [ClientCertGuard], host: &str, path: &str) -> Option<&'a ClientCertGuard> {
+    hosts::best(guards.iter(), |g| {
+        let path_match = g.paths.iter().any(|p| {
+            let prefix = p.trim_end_matches('/');
+            path == prefix || path.starts_with(&format!("{}/", prefix))
+        });
+        if path_match { hosts::rank(&g.sites, host) } else { None }
+    })
+}
+
+/// Check whether a certificate identity is permitted by a guard rule.
+pub fn identity_allowed(guard: &ClientCertGuard, id: &ClientIdentity) -> bool {
+    if id.common_names.iter().any(|cn| guard.allowed_names.iter().any(|n| n == cn)) {
+        return true;
+    }
+    id.emails.iter().any(|email| {
+        let email_lc = email.to_ascii_lowercase();
+        if guard.allowed_emails.iter().any(|e| e.to_ascii_lowercase() == email_lc) {
+            return true;
+        }
+        email_lc
+            .split('@')
+            .nth(1)
+            .is_some_and(|domain| guard.allowed_domains.iter().any(|d| d.to_ascii_lowercase() == domain))
+    })
+}
@@ -13,6 +13,42 @@ fn default_renewal_days() -> u32 {
     30
 }
 
+fn default_acme_challenge() -> String {
+    "http-01".to_string()
+}
+
+fn default_access_log_format() -> String {
+    "combined".to_string()
+}
+
+fn default_access_log_rotate() -> String {
+    "never".to_string()
+}
+
+fn default_access_log_max_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_access_log_keep() -> usize {
+    7
+}
+
+fn default_compression_min_size() -> u16 {
+    1024
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+fn default_backup_interval_hours() -> u64 {
+    24
+}
+
+fn default_backup_keep() -> usize {
+    7
+}
+
 fn default_http_mode() -> String {
     "serve".to_string()
 }
@@ -54,6 +90,93 @@ pub struct AuthGuard {
     pub allowed_emails: Vec<String>,
 }
 
+/// Paths that need a TLS client certificate issued by `client_ca_path`
+/// (`[[client_cert_guard]]`). The certificate's subject CN is matched against
+/// `allowed_names`; its SAN / subject email against `allowed_emails` and
+/// `allowed_domains`, as for auth guards.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ClientCertGuard {
+    /// Hostnames this guard applies to. Empty means all domains.
+    #[serde(default)]
+    pub sites: Vec<String>,
+    #[serde(default)]
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub allowed_names: Vec<String>,
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    #[serde(default)]
+    pub allowed_emails: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Certificate {
+    /// SNI hostnames served with this certificate. `*.example.com` matches one label.
+    pub domains: Vec<String>,
+    pub cert_path: String,
+    pub key_path: String,
+    /// Issue and renew this certificate via ACME. Defaults to `acme_enabled`.
+    #[serde(default)]
+    pub acme: Option<bool>,
+}
+
+/// One socket to accept connections on (`[[listener]]`).
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Listener {
+    /// "ip:port" ("[::]:443" for IPv6), "unix:/path/to.sock", or "systemd" /
+    /// "systemd:N" for all / the Nth socket passed in via `LISTEN_FDS`.
+    pub address: String,
+    /// Serve HTTPS on this socket with the configured certificates.
+    #[serde(default)]
+    pub tls: bool,
+    /// Expect a PROXY protocol v1/v2 header from a TCP load balancer on every
+    /// connection and take the client address from it.
+    #[serde(default)]
+    pub proxy_protocol: bool,
+}
+
+/// Response headers added to every response of one site (`[headers."host"]`).
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SiteHeaders {
+    /// Strict-Transport-Security value; only sent on the HTTPS listener.
+    #[serde(default)]
+    pub hsts: Option<String>,
+    #[serde(default)]
+    pub content_security_policy: Option<String>,
+    #[serde(default)]
+    pub x_frame_options: Option<String>,
+    #[serde(default)]
+    pub referrer_policy: Option<String>,
+    #[serde(default)]
+    pub permissions_policy: Option<String>,
+    /// Any other headers, by name.
+    #[serde(default)]
+    pub custom: HashMap<String, String>,
+}
+
+fn default_rate_limit_by() -> String {
+    "ip".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RateLimit {
+    /// Hostnames this budget applies to. Empty means all domains.
+    #[serde(default)]
+    pub sites: Vec<String>,
+    /// URL path prefixes counted against this budget.
+    pub paths: Vec<String>,
+    /// HTTP methods counted (e.g. ["POST"]). Empty means every method.
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// Requests allowed per `per_seconds`; also the largest burst.
+    pub requests: u32,
+    pub per_seconds: u64,
+    /// "ip" counts per client IP; "user" counts per signed-in auth guard email
+    /// and falls back to the client IP for anonymous requests.
+    #[serde(default = "default_rate_limit_by")]
+    pub by: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PermanentRoom {
     pub name: String,
@@ -62,6 +185,19 @@ pub struct PermanentRoom {
     pub password: Option<String>,
 }
 
+/// `[paths]`: per-feature locations. Relative entries are under `data_dir`;
+/// see `paths::Paths` for the defaults.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PathOverrides {
+    pub database: Option<String>,
+    pub uploads: Option<String>,
+    pub posts: Option<String>,
+    pub drafts: Option<String>,
+    pub blog_images: Option<String>,
+    pub thumb_cache: Option<String>,
+    pub static_dir: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub scope: String,
@@ -71,6 +207,9 @@ pub struct Config {
     pub ssl_port: u16,
     pub ssl_cert_path: Option<String>,
     pub ssl_key_path: Option<String>,
+    /// PEM bundle of CAs whose client certificates HTTPS listeners accept.
+    /// Clients may still connect without one; `client_cert_guards` decide.
+    pub client_ca_path: Option<String>,
     /// Obtain/renew the SSL cert automatically via ACME (Let's Encrypt) using the
     /// HTTP-01 challenge served on the plain-HTTP listener. Writes the issued cert
     /// chain and key to `ssl_cert_path` / `ssl_key_path`. Requires `ssl_enabled`.
@@ -81,11 +220,28 @@ pub struct Config {
     pub acme_contact_email: Option<String>,
     /// Use the Let's Encrypt production directory when true, staging when false.
     pub acme_production: bool,
+    /// ACME directory of another CA (ZeroSSL, step-ca, Pebble). Overrides
+    /// `acme_production` when set.
+    pub acme_directory_url: Option<String>,
+    /// External Account Binding key id and base64url HMAC key, for CAs that
+    /// require registering against an existing account.
+    pub acme_eab_key_id: Option<String>,
+    pub acme_eab_hmac_key: Option<String>,
+    /// PEM file with an extra root CA the ACME client trusts (private CAs, Pebble).
+    pub acme_root_ca: Option<String>,
     /// Where the persisted ACME account credentials are stored (JSON). Reused
     /// across restarts so we don't register a new account every time.
     pub acme_account_path: Option<String>,
     /// Renew when fewer than this many days remain before the cert expires.
     pub acme_renewal_days: u32,
+    /// "http-01" (answered on the plain-HTTP listener) or "tls-alpn-01"
+    /// (answered by the HTTPS listener, so port 80 can stay closed).
+    pub acme_challenge: String,
+    /// Extra per-host certificates selected by SNI; see `certs::cert_groups`.
+    pub certificates: Vec<Certificate>,
+    /// Sockets to serve on. When empty, `scope`, `port` and `ssl_port` pick
+    /// one plain-HTTP and (with `ssl_enabled`) one HTTPS address.
+    pub listeners: Vec<Listener>,
     /// Behaviour of the plain-HTTP listener: "serve" (full app over HTTP),
     /// "redirect" (308 to HTTPS), or "https_only" (only answer ACME challenges).
     pub http_mode: String,
@@ -93,7 +249,12 @@ pub struct Config {
     pub upload_storage_limit: Option<u64>,
     pub browser: bool,
     pub sites: HashMap<String, Vec<(String, Vec<String>)>>,
+    /// Addresses or CIDR ranges allowed per host ("default" for all hosts).
     pub whitelists: HashMap<String, Vec<String>>,
+    /// Addresses or CIDR ranges refused per host; "default" applies to every host.
+    pub blacklists: HashMap<String, Vec<String>>,
+    /// Load balancers / reverse proxies whose X-Forwarded-For is believed.
+    pub trusted_proxies: Vec<String>,
     pub slideshow_autoplay: bool,
     pub slideshow_timer: u64,
     pub domain: String,
@@ -108,10 +269,43 @@ pub struct Config {
     pub permanent_rooms: Option<Vec<PermanentRoom>>,
     pub public_ip: Option<String>,
     pub auth_guards: Vec<AuthGuard>,
+    pub client_cert_guards: Vec<ClientCertGuard>,
     pub guard_redirect_url: Option<String>,
     pub guard_login_page: Option<String>,
     pub admin_dashboards: Vec<AdminDashboard>,
     pub file_guards: Vec<FileGuard>,
+    pub rate_limits: Vec<RateLimit>,
+    /// File the per-request access log is appended to. Unset disables it.
+    pub access_log: Option<String>,
+    /// "combined" (Apache Combined Log Format plus host and latency) or "json".
+    pub access_log_format: String,
+    /// "never", "daily" (UTC) or "size" (at `access_log_max_bytes`).
+    pub access_log_rotate: String,
+    pub access_log_max_bytes: u64,
+    /// How many rotated files to keep.
+    pub access_log_keep: usize,
+    /// Serve Prometheus metrics at `/metrics` on every site.
+    pub metrics: bool,
+    /// Seconds in-flight requests get to finish after SIGTERM / Ctrl-C.
+    pub shutdown_timeout: u64,
+    /// Rebuild templates when files under static/ change and show template
+    /// errors in the browser. Read at startup.
+    pub dev_mode: bool,
+    /// Base for the database, uploads, blog and caches; relative to the config
+    /// file. Read at startup, like `paths`.
+    pub data_dir: Option<String>,
+    pub paths: PathOverrides,
+    /// Directory scheduled backups are written to. Unset disables them.
+    pub backup_dir: Option<String>,
+    pub backup_interval_hours: u64,
+    /// How many scheduled backups to keep in `backup_dir`.
+    pub backup_keep: usize,
+    /// Per-host response compression switch; "default" covers unlisted hosts.
+    pub compression: HashMap<String, bool>,
+    /// Responses smaller than this many bytes are sent uncompressed.
+    pub compression_min_size: u16,
+    /// Security and custom response headers per host; "default" covers the rest.
+    pub headers: HashMap<String, SiteHeaders>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -124,6 +318,8 @@ struct RawConfig {
     ssl_cert_path: Option<String>,
     ssl_key_path: Option<String>,
     #[serde(default)]
+    client_ca_path: Option<String>,
+    #[serde(default)]
     acme_enabled: bool,
     #[serde(default)]
     acme_domains: Vec<String>,
@@ -132,9 +328,23 @@ struct RawConfig {
     #[serde(default)]
     acme_production: bool,
     #[serde(default)]
+    acme_directory_url: Option<String>,
+    #[serde(default)]
+    acme_eab_key_id: Option<String>,
+    #[serde(default)]
+    acme_eab_hmac_key: Option<String>,
+    #[serde(default)]
+    acme_root_ca: Option<String>,
+    #[serde(default)]
     acme_account_path: Option<String>,
     #[serde(default = "default_renewal_days")]
     acme_renewal_days: u32,
+    #[serde(default = "default_acme_challenge")]
+    acme_challenge: String,
+    #[serde(default, rename = "certificate")]
+    certificate: Vec<Certificate>,
+    #[serde(default, rename = "listener")]
+    listener: Vec<Listener>,
     #[serde(default = "default_http_mode")]
     http_mode: String,
     upload_size_limit: Option<Value>,
@@ -158,14 +368,54 @@ struct RawConfig {
     pub guard_login_page: Option<String>,
     #[serde(default)]
     pub auth_guard: Vec<AuthGuard>,
+    #[serde(default, rename = "client_cert_guard")]
+    pub client_cert_guard: Vec<ClientCertGuard>,
     #[serde(default, rename = "admin_dashboard")]
     pub admin_dashboard: Vec<AdminDashboard>,
     #[serde(default, rename = "file_guard")]
     pub file_guard: Vec<FileGuard>,
     #[serde(default)]
+    rate_limit: Vec<RateLimit>,
+    #[serde(default)]
+    access_log: Option<String>,
+    #[serde(default = "default_access_log_format")]
+    access_log_format: String,
+    #[serde(default = "default_access_log_rotate")]
+    access_log_rotate: String,
+    #[serde(default = "default_access_log_max_bytes")]
+    access_log_max_bytes: u64,
+    #[serde(default = "default_access_log_keep")]
+    access_log_keep: usize,
+    #[serde(default)]
+    metrics: bool,
+    #[serde(default = "default_shutdown_timeout")]
+    shutdown_timeout: u64,
+    #[serde(default)]
+    dev_mode: bool,
+    #[serde(default)]
+    data_dir: Option<String>,
+    #[serde(default)]
+    paths: PathOverrides,
+    #[serde(default)]
+    backup_dir: Option<String>,
+    #[serde(default = "default_backup_interval_hours")]
+    backup_interval_hours: u64,
+    #[serde(default = "default_backup_keep")]
+    backup_keep: usize,
+    #[serde(default)]
+    compression: HashMap<String, bool>,
+    #[serde(default = "default_compression_min_size")]
+    compression_min_size: u16,
+    #[serde(default)]
+    headers: HashMap<String, SiteHeaders>,
+    #[serde(default)]
     routes: HashMap<String, RouteValue>,
     #[serde(default)]
     whitelist: HashMap<String, WhitelistValue>,
+    #[serde(default)]
+    blacklist: HashMap<String, WhitelistValue>,
+    #[serde(default)]
+    trusted_proxies: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -182,14 +432,38 @@ enum WhitelistValue {
     DomainMap(HashMap<String, Vec<String>>),
 }
 
+fn flatten_ip_lists(lists: HashMap<String, WhitelistValue>) -> HashMap<String, Vec<String>> {
+    let mut flat = HashMap::new();
+    for (key, value) in lists {
+        match value {
+            WhitelistValue::Ips(ips) => {
+                flat.entry(key).or_insert_with(Vec::new).extend(ips);
+            }
+            WhitelistValue::DomainMap(map) => {
+                let domain_ips = flat.entry(key).or_insert_with(Vec::new);
+                for (_, ips) in map {
+                    domain_ips.extend(ips);
+                }
+            }
+        }
+    }
+    flat
+}
+
 pub fn read_config() -> Option<Config> {
-    let contents = fs::read_to_string("config.toml").ok()?;
-    let raw: RawConfig = toml::from_str(&contents).map_err(|e| {
+    let contents = fs::read_to_string(crate::paths::get().config.as_path()).ok()?;
+    parse_config(&contents).map_err(|e| {
         print_fancy(&[
             ("Error parsing config file: ", ORANGE, vec![]),
             (&format!("{}", e), RED, vec![BOLD])
         ], NewLine);
-    }).ok()?;
+    }).ok()
+}
+
+/// Parse the contents of a config file. Used by `read_config` at startup and by
+/// the hot-reload watcher, which must keep the old config when this fails.
+pub fn parse_config(contents: &str) -> Result<Config, toml::de::Error> {
+    let raw: RawConfig = toml::from_str(contents)?;
     let mut sites = HashMap::new();
     for (key, value) in raw.routes {
         match value {
@@ -204,21 +478,9 @@ pub fn read_config() -> Option<Config> {
             }
         }
     }
-    let mut whitelists = HashMap::new();
-    for (key, value) in raw.whitelist {
-        match value {
-            WhitelistValue::Ips(ips) => {
-                whitelists.entry(key).or_insert_with(Vec::new).extend(ips);
-            }
-            WhitelistValue::DomainMap(map) => {
-                let domain_ips = whitelists.entry(key).or_insert_with(Vec::new);
-                for (_, ips) in map {
-                    domain_ips.extend(ips);
-                }
-            }
-        }
-    }
-    Some(Config {
+    let whitelists = flatten_ip_lists(raw.whitelist);
+    let blacklists = flatten_ip_lists(raw.blacklist);
+    Ok(Config {
         scope: raw.scope,
         ip: raw.ip,
         port: raw.port,
@@ -226,18 +488,28 @@ pub fn read_config() -> Option<Config> {
         ssl_port: raw.ssl_port,
         ssl_cert_path: raw.ssl_cert_path,
         ssl_key_path: raw.ssl_key_path,
+        client_ca_path: raw.client_ca_path,
         acme_enabled: raw.acme_enabled,
         acme_domains: raw.acme_domains,
         acme_contact_email: raw.acme_contact_email,
         acme_production: raw.acme_production,
+        acme_directory_url: raw.acme_directory_url,
+        acme_eab_key_id: raw.acme_eab_key_id,
+        acme_eab_hmac_key: raw.acme_eab_hmac_key,
+        acme_root_ca: raw.acme_root_ca,
         acme_account_path: raw.acme_account_path,
         acme_renewal_days: raw.acme_renewal_days,
+        acme_challenge: raw.acme_challenge.trim().to_lowercase(),
+        certificates: raw.certificate,
+        listeners: raw.listener,
         http_mode: raw.http_mode.trim().to_lowercase(),
         upload_size_limit: raw.upload_size_limit,
         upload_storage_limit: raw.upload_storage_limit,
         browser: raw.browser,
         sites,
         whitelists,
+        blacklists,
+        trusted_proxies: raw.trusted_proxies,
         slideshow_autoplay: raw.slideshow_autoplay,
         slideshow_timer: raw.slideshow_timer,
         domain: raw.domain.trim().to_string(),
@@ -252,9 +524,27 @@ pub fn read_config() -> Option<Config> {
         permanent_rooms: raw.permanent_rooms,
         public_ip: raw.public_ip,
         auth_guards: raw.auth_guard,
+        client_cert_guards: raw.client_cert_guard,
         guard_redirect_url: raw.guard_redirect_url,
         guard_login_page: raw.guard_login_page,
         admin_dashboards: raw.admin_dashboard,
         file_guards: raw.file_guard,
+        rate_limits: raw.rate_limit,
+        access_log: raw.access_log,
+        access_log_format: raw.access_log_format.trim().to_lowercase(),
+        access_log_rotate: raw.access_log_rotate.trim().to_lowercase(),
+        access_log_max_bytes: raw.access_log_max_bytes,
+        access_log_keep: raw.access_log_keep,
+        metrics: raw.metrics,
+        shutdown_timeout: raw.shutdown_timeout,
+        dev_mode: raw.dev_mode,
+        data_dir: raw.data_dir,
+        paths: raw.paths,
+        backup_dir: raw.backup_dir,
+        backup_interval_hours: raw.backup_interval_hours,
+        backup_keep: raw.backup_keep,
+        compression: raw.compression,
+        compression_min_size: raw.compression_min_size,
+        headers: raw.headers,
     })
 }
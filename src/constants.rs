@@ -23,8 +23,39 @@ pub static STREAM_DASHBOARD: &str = include_str!("templates/static/stream.html")
 pub static ADMIN_DASHBOARD: &str = include_str!("templates/static/admin_dashboard.html");
 pub static FILE_GATE: &str = include_str!("templates/static/file-gate.html");
 pub static STREAM_VIEWER: &str = include_str!("templates/static/view.html");
+pub static EXAMPLE_INTERACTION: &str = include_str!("templates/static/interaction.html");
+pub static EXAMPLE_FORUM: &str = include_str!("templates/static/forum.html");
 pub static EXAMPLE_MARKDOWN: &str = include_str!("templates/static/documents/example.md");
 pub static EXAMPLE_FIRST_SLIDE: &str = include_str!("templates/static/slides/0.md");
 pub static EXAMPLE_SECOND_SLIDE: &str = include_str!("templates/static/slides/1.md");
 pub static EXAMPLE_FIRST_POST: &str = include_str!("templates/static/posts/Python.md");
 pub static EXAMPLE_SECOND_POST: &str = include_str!("templates/static/posts/Rust.md");
+
+/// Built-in Tera templates by name. Files under `static/` with the same name
+/// take precedence, so a fresh install renders every page without them.
+pub static EMBEDDED_TEMPLATES: &[(&str, &str)] = &[
+    ("base.html", EXAMPLE_BASE),
+    ("home.html", EXAMPLE_HOME),
+    ("stuff.html", EXAMPLE_STUFF),
+    ("thumb.html", EXAMPLE_THUMB),
+    ("pdf.html", EXAMPLE_PDF),
+    ("blog.html", EXAMPLE_BLOG),
+    ("downloads.html", EXAMPLE_DOWNLOADS),
+    ("watch.html", EXAMPLE_WATCH),
+    ("playlists.html", PLAYLISTS),
+    ("upload.html", UPLOAD),
+    ("uploads.html", FILES),
+    ("error.html", EXAMPLE_ERROR),
+    ("forum.html", EXAMPLE_FORUM),
+    ("forum_index.html", EXAMPLE_FORUM_INDEX),
+    ("forum_category.html", EXAMPLE_FORUM_CATEGORY),
+    ("forum_new_topic.html", EXAMPLE_FORUM_NEW_TOPIC),
+    ("topic.html", EXAMPLE_FORUM_TOPIC),
+    ("forum_edit_post.html", EXAMPLE_FORUM_EDIT_POST),
+    ("forum_admin.html", EXAMPLE_FORUM_ADMIN),
+    ("interaction.html", EXAMPLE_INTERACTION),
+    ("stream.html", STREAM_DASHBOARD),
+    ("view.html", STREAM_VIEWER),
+    ("admin_dashboard.html", ADMIN_DASHBOARD),
+    ("file-gate.html", FILE_GATE),
+];
@@ -1,7 +1,6 @@
 use axum::{
     extract::{State, Query, Multipart},
     response::{Html, IntoResponse, Redirect},
-    http::StatusCode,
 };
 use sha2::{Sha256, Digest};
 use axum_extra::extract::CookieJar;
@@ -146,7 +145,7 @@ pub async fn file_gate_page(
 
     match state.tera.render("file-gate.html", &ctx) {
         Ok(html) => Html(html).into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Gate template missing — add static/file-gate.html").into_response(),
+        Err(e) => state.tera.error_response("file-gate.html", &e),
     }
 }
 
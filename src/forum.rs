@@ -4,11 +4,11 @@ use serde::{Deserialize, Serialize};
 use axum::{
     extract::{State, Form, Query, Path},
     response::{Html, IntoResponse, Redirect},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
 };
 use crate::AppState;
+use crate::forum_sessions;
 use axum_extra::extract::CookieJar;
-use axum_extra::extract::cookie::Cookie;
 use std::sync::Arc;
 use chrono::{DateTime, Utc};
 use bcrypt::{hash, verify, DEFAULT_COST};
@@ -65,7 +65,13 @@ pub struct ForumConfig {
 }
 
 pub fn read_forum_config() -> ForumConfig {
-    let contents = fs::read_to_string("forum.toml").unwrap_or_else(|_| {
+    load_forum_config().expect("Failed to parse forum.toml")
+}
+
+/// Like `read_forum_config`, but hands parse errors back to the caller instead of
+/// panicking so a bad edit during hot reload can't take the server down.
+pub fn load_forum_config() -> Result<ForumConfig, toml::de::Error> {
+    let contents = fs::read_to_string(&crate::paths::get().forum_config).unwrap_or_else(|_| {
         r#"
         [[category]]
         id = "general"
@@ -73,7 +79,7 @@ pub fn read_forum_config() -> ForumConfig {
         description = "Default category"
         "#.to_string()
     });
-    toml::from_str(&contents).expect("Failed to parse forum.toml")
+    toml::from_str(&contents)
 }
 
 #[derive(Debug, Serialize)]
@@ -278,103 +284,18 @@ pub struct CreateCategoryForm {
 
 pub type ForumDb = Arc<SqlitePool>;
 
+/// Open forum.db and bring its schema up to date (see `migrations`).
 pub async fn init_db() -> ForumDb {
+    let path = &crate::paths::get().database;
+    if let Err(e) = crate::paths::ensure_parent(path) {
+        panic!("could not create the directory for {}: {e}", path.display());
+    }
     let opts = SqliteConnectOptions::new()
-        .filename("forum.db")
+        .filename(path)
         .create_if_missing(true)
         .journal_mode(SqliteJournalMode::Wal);
     let pool = SqlitePool::connect_with(opts).await.unwrap();
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS categories (
-            id TEXT PRIMARY KEY,
-            name TEXT NOT NULL,
-            description TEXT NOT NULL
-        )"
-    ).execute(&pool).await.unwrap();
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS users (
-            username TEXT PRIMARY KEY,
-            email TEXT NOT NULL,
-            password_hash TEXT NOT NULL,
-            is_verified INTEGER NOT NULL DEFAULT 0,
-            verification_token TEXT NOT NULL,
-            role TEXT NOT NULL DEFAULT 'Member',
-            is_banned INTEGER NOT NULL DEFAULT 0
-        )"
-    ).execute(&pool).await.unwrap();
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS posts (
-            id TEXT PRIMARY KEY,
-            category_id TEXT NOT NULL,
-            title TEXT NOT NULL,
-            content TEXT NOT NULL,
-            author TEXT NOT NULL,
-            created_at TEXT NOT NULL,
-            updated_at TEXT,
-            is_locked INTEGER NOT NULL DEFAULT 0,
-            min_reply_role TEXT NOT NULL DEFAULT 'Member',
-            view_count INTEGER NOT NULL DEFAULT 0
-        )"
-    ).execute(&pool).await.unwrap();
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS replies (
-            id TEXT PRIMARY KEY,
-            thread_id TEXT NOT NULL,
-            content TEXT NOT NULL,
-            author TEXT NOT NULL,
-            created_at TEXT NOT NULL
-        )"
-    ).execute(&pool).await.unwrap();
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS stream_keys (
-            username TEXT PRIMARY KEY,
-            stream_key TEXT UNIQUE NOT NULL
-        )"
-    ).execute(&pool).await.unwrap();
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS guard_sessions (
-            token TEXT PRIMARY KEY,
-            email TEXT NOT NULL,
-            created_at TEXT NOT NULL,
-            expires_at TEXT NOT NULL
-        )"
-    ).execute(&pool).await.unwrap();
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS access_rules (
-            id TEXT PRIMARY KEY,
-            domain TEXT NOT NULL DEFAULT '',
-            path TEXT NOT NULL,
-            email TEXT,
-            email_domain TEXT,
-            created_by TEXT NOT NULL,
-            created_at TEXT NOT NULL
-        )"
-    ).execute(&pool).await.unwrap();
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS dashboard_editors (
-            email TEXT PRIMARY KEY,
-            granted_by TEXT NOT NULL,
-            granted_at TEXT NOT NULL
-        )"
-    ).execute(&pool).await.unwrap();
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS file_gate_sessions (
-            token TEXT PRIMARY KEY,
-            gate_hash TEXT NOT NULL,
-            created_at TEXT NOT NULL,
-            expires_at TEXT NOT NULL
-        )"
-    ).execute(&pool).await.unwrap();
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS db_file_guards (
-            id TEXT PRIMARY KEY,
-            label TEXT NOT NULL,
-            paths TEXT NOT NULL,
-            hash TEXT NOT NULL,
-            created_by TEXT NOT NULL,
-            created_at TEXT NOT NULL
-        )"
-    ).execute(&pool).await.unwrap();
+    crate::migrations::run_or_exit(&pool).await;
     Arc::new(pool)
 }
 
@@ -401,7 +322,7 @@ pub async fn get_categories(db: &ForumDb) -> Vec<Category> {
 }
 
 pub async fn get_current_user(state: &Arc<AppState>, jar: &CookieJar) -> Option<User> {
-    let username = jar.get("username")?.value().to_string();
+    let username = crate::forum_sessions::current_username(&state.forum_db, jar).await?;
     let row = sqlx::query_as::<_, DbUser>("SELECT * FROM users WHERE username = ?")
         .bind(&username)
         .fetch_optional(&*state.forum_db)
@@ -502,10 +423,7 @@ pub async fn view_thread(
     context.insert("base_path", &"/forum");
     match state.tera.render("topic.html", &context) {
         Ok(rendered) => Html(rendered).into_response(),
-        Err(e) => {
-            eprintln!("Tera error rendering topic.html: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
-        }
+        Err(e) => state.tera.error_response("topic.html", &e),
     }
 }
 
@@ -1110,6 +1028,7 @@ pub async fn login_form(State(_state): State<Arc<AppState>>) -> Html<String> {
 pub async fn login(
     State(state): State<Arc<AppState>>,
     jar: CookieJar,
+    headers: HeaderMap,
     Form(form): Form<LoginForm>
 ) -> impl IntoResponse {
     let row = sqlx::query_as::<_, DbUser>("SELECT * FROM users WHERE username = ?")
@@ -1126,19 +1045,19 @@ pub async fn login(
             return Html("<h1>Your account has been banned from this forum.</h1>").into_response();
         }
         if verify(form.password, &user.password_hash).unwrap() {
-            let cookie = Cookie::build(("username", user.username)).path("/").http_only(true).build();
-            return (jar.add(cookie), Redirect::to("/forum")).into_response();
+            let jar = forum_sessions::start(&state.forum_db, jar, &headers, &user.username).await;
+            return (jar, Redirect::to("/forum")).into_response();
         }
     }
     Html("<h1>Invalid Credentials</h1>").into_response()
 }
 
 pub async fn logout(
-    State(_state): State<Arc<AppState>>,
+    State(state): State<Arc<AppState>>,
     jar: CookieJar
 ) -> impl IntoResponse {
-    let cookie = Cookie::build("username").path("/").build();
-    (jar.remove(cookie), Redirect::to("/forum"))
+    let jar = forum_sessions::end(&state.forum_db, jar).await;
+    (jar, Redirect::to("/forum"))
 }
 
 pub async fn new_post_form(
@@ -1163,13 +1082,7 @@ pub async fn new_post_form(
     context.insert("base_path", &base);
     match state.tera.render("forum_new_topic.html", &context) {
         Ok(rendered) => Html(rendered).into_response(),
-        Err(e) => {
-            eprintln!("Tera error: {}", e);
-            if let Some(source) = std::error::Error::source(&e) {
-                eprintln!("Caused by: {}", source);
-            }
-            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
-        }
+        Err(e) => state.tera.error_response("forum_new_topic.html", &e),
     }
 }
 
@@ -1268,6 +1181,7 @@ pub async fn login_google(State(state): State<Arc<AppState>>) -> impl IntoRespon
 pub async fn callback_google(
     State(state): State<Arc<AppState>>,
     jar: CookieJar,
+    headers: HeaderMap,
     Query(query): Query<GoogleCallback>,
 ) -> impl IntoResponse {
     let config = &state.config;
@@ -1343,11 +1257,8 @@ pub async fn callback_google(
         final_username
     };
 
-    let cookie = Cookie::build(("username", username))
-        .path("/")
-        .http_only(true)
-        .build();
-    (jar.add(cookie), Redirect::to("/forum")).into_response()
+    let jar = forum_sessions::start(&state.forum_db, jar, &headers, &username).await;
+    (jar, Redirect::to("/forum")).into_response()
 }
 
 pub async fn board_index(
@@ -1366,10 +1277,7 @@ pub async fn board_index(
     context.insert("base_path", &"/forum");
     match state.tera.render("forum_index.html", &context) {
         Ok(rendered) => Html(rendered).into_response(),
-        Err(e) => {
-            eprintln!("Tera error rendering forum_index.html: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
-        }
+        Err(e) => state.tera.error_response("forum_index.html", &e),
     }
 }
 
@@ -1403,10 +1311,7 @@ pub async fn view_category(
     context.insert("base_path", &"/forum");
     match state.tera.render("forum_category.html", &context) {
         Ok(rendered) => Html(rendered).into_response(),
-        Err(e) => {
-            eprintln!("Tera error rendering forum_category.html: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
-        }
+        Err(e) => state.tera.error_response("forum_category.html", &e),
     }
 }
 
@@ -1447,7 +1352,7 @@ pub async fn admin_panel(
     context.insert("base_path", &"/forum");
     match state.tera.render("forum_admin.html", &context) {
         Ok(rendered) => Html(rendered).into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        Err(e) => state.tera.error_response("forum_admin.html", &e),
     }
 }
 
@@ -1527,7 +1432,7 @@ pub async fn admin_edit_post_form(
     context.insert("base_path", &"/forum");
     match state.tera.render("forum_edit_post.html", &context) {
         Ok(rendered) => Html(rendered).into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        Err(e) => state.tera.error_response("forum_edit_post.html", &e),
     }
 }
 
@@ -1586,7 +1491,10 @@ pub async fn admin_set_role(
         .bind(&username)
         .execute(&*state.forum_db)
         .await;
-    Redirect::to("/forum/admin").into_response()
+    // Roles are read on every request, so the user's sessions simply carry
+    // the new one; the Owner's session that made the change is rotated.
+    let jar = forum_sessions::rotate(&state.forum_db, jar).await;
+    (jar, Redirect::to("/forum/admin")).into_response()
 }
 
 pub async fn admin_ban_user(
@@ -1616,6 +1524,7 @@ pub async fn admin_ban_user(
         .bind(&username)
         .execute(&*state.forum_db)
         .await;
+    forum_sessions::revoke_user(&state.forum_db, &username).await;
     Redirect::to("/forum/admin").into_response()
 }
 
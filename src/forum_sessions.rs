@@ -0,0 +1,279 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect},
+};
+use axum_extra::extract::CookieJar;
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use chrono::{Duration, Utc};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::forum::ForumDb;
+
+pub const FORUM_SESSION_COOKIE: &str = "forum_session";
+/// The pre-session cookie that held a bare username. It is no longer trusted
+/// and is cleared whenever a session is issued or ended.
+pub const LEGACY_USERNAME_COOKIE: &str = "username";
+const SESSION_DAYS: i64 = 30;
+/// `last_seen` is only rewritten when older than this, to keep page views
+/// from turning into database writes.
+const LAST_SEEN_RESOLUTION_SECS: i64 = 300;
+
+/// Only a digest of the token is stored, so a leaked database does not hand
+/// out live sessions.
+fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn new_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn session_cookie(token: String) -> Cookie<'static> {
+    Cookie::build((FORUM_SESSION_COOKIE, token))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .build()
+}
+
+fn user_agent(headers: &HeaderMap) -> String {
+    headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("unknown device")
+        .chars()
+        .take(200)
+        .collect()
+}
+
+/// Username of an unexpired session token. Expired rows are deleted on sight.
+pub async fn lookup(db: &ForumDb, token: &str) -> Option<String> {
+    let hash = token_hash(token);
+    let row: Option<(String, String, String)> = sqlx::query_as(
+        "SELECT id, username, expires_at FROM forum_sessions WHERE token_hash = ?",
+    )
+    .bind(&hash)
+    .fetch_optional(&**db)
+    .await
+    .ok()?;
+    let (id, username, expires_str) = row?;
+    let expires = chrono::DateTime::parse_from_rfc3339(&expires_str).ok()?;
+    let now = Utc::now();
+    if expires < now {
+        let _ = sqlx::query("DELETE FROM forum_sessions WHERE id = ?")
+            .bind(&id)
+            .execute(&**db)
+            .await;
+        return None;
+    }
+    let stale = (now - Duration::seconds(LAST_SEEN_RESOLUTION_SECS)).to_rfc3339();
+    let _ = sqlx::query("UPDATE forum_sessions SET last_seen = ? WHERE id = ? AND last_seen < ?")
+        .bind(now.to_rfc3339())
+        .bind(&id)
+        .bind(&stale)
+        .execute(&**db)
+        .await;
+    crate::access_log::note_forum_user(&username);
+    Some(username)
+}
+
+/// Username behind the request's forum session cookie.
+pub async fn current_username(db: &ForumDb, jar: &CookieJar) -> Option<String> {
+    let token = jar.get(FORUM_SESSION_COOKIE)?.value().to_string();
+    lookup(db, &token).await
+}
+
+/// Same as `current_username`, for middleware that only has the raw
+/// Cookie header.
+pub async fn username_from_header(db: &ForumDb, cookie_header: &str) -> Option<String> {
+    let token = crate::auth_guard::extract_cookie_value(cookie_header, FORUM_SESSION_COOKIE)?;
+    lookup(db, &token).await
+}
+
+/// Sign `username` in: the session the browser already had (if any) is
+/// revoked and a fresh token is issued, so a token planted before login is
+/// never promoted to an authenticated one.
+pub async fn start(db: &ForumDb, jar: CookieJar, headers: &HeaderMap, username: &str) -> CookieJar {
+    if let Some(old) = jar.get(FORUM_SESSION_COOKIE) {
+        revoke_token(db, old.value()).await;
+    }
+    let token = new_token();
+    let now = Utc::now();
+    let _ = sqlx::query(
+        "INSERT INTO forum_sessions (id, token_hash, username, user_agent, created_at, last_seen, expires_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(token_hash(&token))
+    .bind(username)
+    .bind(user_agent(headers))
+    .bind(now.to_rfc3339())
+    .bind(now.to_rfc3339())
+    .bind((now + Duration::days(SESSION_DAYS)).to_rfc3339())
+    .execute(&**db)
+    .await;
+    jar.remove(Cookie::build(LEGACY_USERNAME_COOKIE).path("/").build())
+        .add(session_cookie(token))
+}
+
+/// Revoke the browser's session and clear its cookies.
+pub async fn end(db: &ForumDb, jar: CookieJar) -> CookieJar {
+    if let Some(cookie) = jar.get(FORUM_SESSION_COOKIE) {
+        revoke_token(db, cookie.value()).await;
+    }
+    jar.remove(Cookie::build(FORUM_SESSION_COOKIE).path("/").build())
+        .remove(Cookie::build(LEGACY_USERNAME_COOKIE).path("/").build())
+}
+
+pub async fn revoke_token(db: &ForumDb, token: &str) {
+    let _ = sqlx::query("DELETE FROM forum_sessions WHERE token_hash = ?")
+        .bind(token_hash(token))
+        .execute(&**db)
+        .await;
+}
+
+/// Swap the browser's session token for a fresh one, keeping the session
+/// itself. Done after a privilege change so a token seen before it stops
+/// working.
+pub async fn rotate(db: &ForumDb, jar: CookieJar) -> CookieJar {
+    let Some(old) = jar.get(FORUM_SESSION_COOKIE).map(|c| c.value().to_string()) else {
+        return jar;
+    };
+    let token = new_token();
+    let updated = sqlx::query("UPDATE forum_sessions SET token_hash = ? WHERE token_hash = ?")
+        .bind(token_hash(&token))
+        .bind(token_hash(&old))
+        .execute(&**db)
+        .await;
+    match updated {
+        Ok(r) if r.rows_affected() == 1 => jar.add(session_cookie(token)),
+        _ => jar,
+    }
+}
+
+/// Sign a user out everywhere, e.g. after a ban or password reset so the next
+/// request starts from a fresh login.
+pub async fn revoke_user(db: &ForumDb, username: &str) {
+    let _ = sqlx::query("DELETE FROM forum_sessions WHERE username = ?")
+        .bind(username)
+        .execute(&**db)
+        .await;
+}
+
+#[derive(sqlx::FromRow)]
+struct SessionRow {
+    id: String,
+    token_hash: String,
+    user_agent: String,
+    created_at: String,
+    last_seen: String,
+    expires_at: String,
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// ─── Handlers ────────────────────────────────────────────────────────────────
+
+/// GET /forum/sessions — the signed-in user's sessions, one per device.
+pub async fn list_sessions(
+    State(state): State<Arc<AppState>>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let Some(username) = current_username(&state.forum_db, &jar).await else {
+        return Redirect::to("/forum/login").into_response();
+    };
+    let current_hash = jar.get(FORUM_SESSION_COOKIE).map(|c| token_hash(c.value()));
+    let sessions: Vec<SessionRow> = sqlx::query_as(
+        "SELECT id, token_hash, user_agent, created_at, last_seen, expires_at
+         FROM forum_sessions WHERE username = ? ORDER BY last_seen DESC",
+    )
+    .bind(&username)
+    .fetch_all(&*state.forum_db)
+    .await
+    .unwrap_or_default();
+
+    let mut rows = String::new();
+    for s in &sessions {
+        let this_device = current_hash.as_deref() == Some(s.token_hash.as_str());
+        let action = if this_device {
+            "<em>this device</em>".to_string()
+        } else {
+            format!(
+                r#"<form method="post" action="/forum/sessions/{id}/revoke"><button type="submit">Revoke</button></form>"#,
+                id = escape_html(&s.id)
+            )
+        };
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(&s.user_agent),
+            escape_html(&s.created_at),
+            escape_html(&s.last_seen),
+            escape_html(&s.expires_at),
+            action,
+        ));
+    }
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Sessions — {user}</title>
+    <link rel="stylesheet" type="text/css" href="https://thomasf.github.io/solarized-css/solarized-dark.min.css">
+</head>
+<body>
+    <h1>Signed-in devices</h1>
+    <p>Signed in as <strong>{user}</strong>. Revoke any session you don't recognise.</p>
+    <table>
+        <tr><th>Device</th><th>Signed in</th><th>Last seen</th><th>Expires</th><th></th></tr>
+        {rows}
+    </table>
+    <form method="post" action="/forum/sessions/revoke-others"><button type="submit">Sign out all other devices</button></form>
+    <p><a href="/forum">&laquo; Back to the forum</a></p>
+</body>
+</html>"#,
+        user = escape_html(&username),
+    ))
+    .into_response()
+}
+
+/// POST /forum/sessions/{id}/revoke
+pub async fn revoke_session(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let Some(username) = current_username(&state.forum_db, &jar).await else {
+        return (StatusCode::UNAUTHORIZED, "Login required").into_response();
+    };
+    let _ = sqlx::query("DELETE FROM forum_sessions WHERE id = ? AND username = ?")
+        .bind(&id)
+        .bind(&username)
+        .execute(&*state.forum_db)
+        .await;
+    Redirect::to("/forum/sessions").into_response()
+}
+
+/// POST /forum/sessions/revoke-others
+pub async fn revoke_other_sessions(
+    State(state): State<Arc<AppState>>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let Some(username) = current_username(&state.forum_db, &jar).await else {
+        return (StatusCode::UNAUTHORIZED, "Login required").into_response();
+    };
+    let current_hash = jar.get(FORUM_SESSION_COOKIE).map(|c| token_hash(c.value())).unwrap_or_default();
+    let _ = sqlx::query("DELETE FROM forum_sessions WHERE username = ? AND token_hash != ?")
+        .bind(&username)
+        .bind(&current_hash)
+        .execute(&*state.forum_db)
+        .await;
+    Redirect::to("/forum/sessions").into_response()
+}
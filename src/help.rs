@@ -9,7 +9,28 @@ pub fn print_help(_binaryname: String) {
     print_fancy(&[
         ("This program is designed to be a modular web service.\n", CYAN, vec![]),
         ("All paths and routes are configured in config.toml\n", CYAN, vec![]),
-        ("If config.toml does not exist, an example project structure can be created.\n\n", CYAN, vec![]),
+        ("Pass ", CYAN, vec![]),
+        ("--config <file>", VIOLET, vec![]),
+        (" to use another config file; its data_dir and [paths] decide where data is kept.\n", CYAN, vec![]),
+        ("If config.toml does not exist, an example project structure can be created.\n", CYAN, vec![]),
+        ("Run ", CYAN, vec![]),
+        ("webify check", VIOLET, vec![]),
+        (" to validate config.toml and its templates without starting the server.\n", CYAN, vec![]),
+        ("Run ", CYAN, vec![]),
+        ("webify db migrate --dry-run", VIOLET, vec![]),
+        (" to list pending forum.db schema migrations (drop --dry-run to apply them).\n", CYAN, vec![]),
+        ("Run ", CYAN, vec![]),
+        ("webify user", VIOLET, vec![]),
+        (", ", CYAN, vec![]),
+        ("webify editor", VIOLET, vec![]),
+        (" or ", CYAN, vec![]),
+        ("webify session", VIOLET, vec![]),
+        (" to manage forum users, dashboard editors and sessions from the shell.\n", CYAN, vec![]),
+        ("Run ", CYAN, vec![]),
+        ("webify backup <file.tar.zst>", VIOLET, vec![]),
+        (" or ", CYAN, vec![]),
+        ("webify restore <file.tar.zst>", VIOLET, vec![]),
+        (" to save or bring back the database, uploads, posts and certificates.\n\n", CYAN, vec![]),
         ("The ", CYAN, vec![]),
         ("config.toml", VIOLET, vec![]),
         (" file should contain something similar to the following.\n\n", CYAN, vec![]),
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+/// How specifically `pattern` names `host`, or `None` when it doesn't cover it.
+/// This is the one definition of host patterns for routes, IP lists,
+/// compression, headers, guards, dashboards and rate limits, and it agrees with
+/// the SNI certificate resolver: a pattern is an exact hostname or
+/// `*.example.com`, which covers exactly one label below example.com
+/// (`a.example.com`, not `a.b.example.com` or example.com itself), as a TLS
+/// wildcard certificate does. Exact names rank above the wildcard, and
+/// `lookup` falls back to "default" after both.
+fn specificity(pattern: &str, host: &str) -> Option<usize> {
+    if pattern.eq_ignore_ascii_case(host) {
+        return Some(usize::MAX);
+    }
+    let suffix = pattern.strip_prefix("*.")?;
+    let (label, parent) = host.split_once('.')?;
+    let covered = !label.is_empty() && parent.eq_ignore_ascii_case(suffix);
+    covered.then_some(1 + suffix.len())
+}
+
+/// True when the host pattern covers `host`.
+pub fn matches(pattern: &str, host: &str) -> bool {
+    specificity(pattern, host).is_some()
+}
+
+/// Rank of a `sites`/`domain` filter for `host`: `Some(0)` for an empty filter,
+/// which applies everywhere like "default", higher for the best matching entry,
+/// `None` when no entry covers the host.
+pub fn rank(patterns: &[String], host: &str) -> Option<usize> {
+    if patterns.is_empty() {
+        return Some(0);
+    }
+    patterns.iter().filter_map(|p| specificity(p, host)).max()
+}
+
+/// `rank` for a single `domain` field, where an empty string means every host.
+pub fn rank_domain(domain: &str, host: &str) -> Option<usize> {
+    if domain.is_empty() { Some(0) } else { specificity(domain, host) }
+}
+
+/// The entry of a per-host map that covers `host` most specifically: the exact
+/// name, else the matching wildcard. "default" is not considered.
+pub fn find<'a, V>(map: &'a HashMap<String, V>, host: &str) -> Option<(&'a str, &'a V)> {
+    map.iter()
+        .filter(|(key, _)| key.as_str() != "default")
+        .filter_map(|(key, value)| specificity(key, host).map(|s| (s, key.as_str(), value)))
+        .max_by_key(|(s, key, _)| (*s, std::cmp::Reverse(*key)))
+        .map(|(_, key, value)| (key, value))
+}
+
+/// Per-host lookup with the documented precedence: exact host, then wildcard,
+/// then "default".
+pub fn lookup<'a, V>(map: &'a HashMap<String, V>, host: &str) -> Option<&'a V> {
+    find(map, host).map(|(_, value)| value).or_else(|| map.get("default"))
+}
+
+/// The first item with the best `rank`, so an exact host beats a wildcard and
+/// both beat an unrestricted entry; ties go to the earliest in config order.
+pub fn best<'a, T>(items: impl Iterator<Item = &'a T>, rank: impl Fn(&T) -> Option<usize>) -> Option<&'a T> {
+    let mut best: Option<(usize, &T)> = None;
+    for item in items {
+        if let Some(r) = rank(item)
+            && best.is_none_or(|(b, _)| r > b)
+        {
+            best = Some((r, item));
+        }
+    }
+    best.map(|(_, item)| item)
+}
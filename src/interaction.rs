@@ -18,6 +18,7 @@ use std::pin::Pin;
 use std::future::Future;
 
 use crate::AppState;
+use crate::config::PermanentRoom;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
@@ -114,6 +115,41 @@ impl InteractionState {
             Box::pin(handler(args, tx))
         }));
     }
+    /// Create any configured permanent rooms that don't exist yet. Rooms that are
+    /// already open (with users connected) are left untouched.
+    pub async fn ensure_permanent_rooms(&self, permanent_rooms: &[PermanentRoom]) {
+        let mut rooms = self.rooms.write().await;
+        for room_config in permanent_rooms {
+            // Generate a simple ID from the name (slugify)
+            let room_id = room_config.name.trim().to_lowercase().replace(" ", "-");
+            if rooms.contains_key(&room_id) {
+                continue;
+            }
+            let (tx, _rx) = broadcast::channel(100);
+            let room = Room {
+                id: room_id.clone(),
+                label: room_config.name.clone(),
+                tx,
+                created_at: Utc::now(),
+                users: HashMap::new(),
+                max_controllers: room_config.max_controllers,
+                max_doers: room_config.max_doers,
+                current_color: "#808080".to_string(),
+                password: room_config.password.clone(),
+            };
+            rooms.insert(room_id, room);
+        }
+    }
+    /// Post a system message to every room, e.g. before the server shuts down.
+    pub async fn broadcast_system(&self, message: &str) {
+        let rooms = self.rooms.read().await;
+        for room in rooms.values() {
+            let _ = room.tx.send(format!(
+                r###"<div hx-swap-oob="beforeend:#chat-container"><div class="system-msg" style="color: #b58900; font-style: italic;">{}</div></div>"###,
+                message
+            ));
+        }
+    }
 }
 
 fn render_room_view(room_id: &str, role: Role, username: &str, current_color: &str) -> String {
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use axum::http::HeaderMap;
+use solarized::{
+    print_fancy,
+    CYAN, ORANGE, RED, VIOLET,
+    BOLD,
+    PrintMode::NewLine,
+};
+
+/// Fold IPv4-mapped IPv6 addresses (`::ffff:1.2.3.4`, what a dual-stack `[::]`
+/// listener reports for IPv4 clients) back to plain IPv4.
+pub fn normalize(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+/// A single address or CIDR range from `[whitelist]`, `[blacklist]` or
+/// `trusted_proxies`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for IpNet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("\"{s}\" is not an IP address or CIDR range"))?;
+        let addr = normalize(addr);
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("\"{s}\" has an invalid prefix length"))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl IpNet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (net, ip, bits) = match (self.addr, normalize(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => (u32::from(net) as u128, u32::from(ip) as u128, 32),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => (u128::from(net), u128::from(ip), 128),
+            _ => return false,
+        };
+        if self.prefix == 0 {
+            return true;
+        }
+        let shift = bits - self.prefix as u32;
+        net >> shift == ip >> shift
+    }
+}
+
+/// A parsed `[whitelist]` or `[blacklist]` entry list. Entries that fail to
+/// parse are reported and dropped, but the list still counts as configured so
+/// a typo locks a whitelist rather than opening it.
+#[derive(Debug, Clone, Default)]
+pub struct IpList {
+    nets: Vec<IpNet>,
+    configured: bool,
+}
+
+impl IpList {
+    pub fn parse(entries: &[String], section: &str) -> Self {
+        let nets = entries
+            .iter()
+            .filter_map(|entry| match entry.parse::<IpNet>() {
+                Ok(net) => Some(net),
+                Err(e) => {
+                    print_fancy(&[
+                        ("Error", RED, vec![BOLD]),
+                        (": ", CYAN, vec![]),
+                        (section, VIOLET, vec![]),
+                        (": ", CYAN, vec![]),
+                        (&e, ORANGE, vec![]),
+                    ], NewLine);
+                    None
+                }
+            })
+            .collect();
+        Self { nets, configured: !entries.is_empty() }
+    }
+
+    pub fn nets(&self) -> &[IpNet] {
+        &self.nets
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.configured
+    }
+
+    pub fn matches(&self, ip: IpAddr) -> bool {
+        self.nets.iter().any(|net| net.contains(ip))
+    }
+}
+
+/// Parse every host's list of a `[whitelist]`/`[blacklist]` style section.
+pub fn parse_lists(lists: &HashMap<String, Vec<String>>, section: &str) -> HashMap<String, IpList> {
+    lists
+        .iter()
+        .map(|(host, entries)| (host.clone(), IpList::parse(entries, &format!("{section}.\"{host}\""))))
+        .collect()
+}
+
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim();
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|sa| sa.ip()))
+        .ok()
+        .map(normalize)
+}
+
+/// Request extension carrying `client_ip`'s answer, set by the outer router so
+/// the reverse proxy forwards the same address the IP lists and rate limiter
+/// saw.
+#[derive(Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+/// The address of the real client. Starting from the TCP peer, walk
+/// `X-Forwarded-For` right to left for as long as the current hop is a trusted
+/// proxy; the first untrusted hop is the client. Untrusted peers can't spoof
+/// their address this way because their header is never consulted.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let mut client = normalize(peer);
+    if trusted_proxies.is_empty() {
+        return client;
+    }
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+    for hop in hops.into_iter().rev() {
+        if !trusted_proxies.iter().any(|net| net.contains(client)) {
+            break;
+        }
+        match parse_hop(hop) {
+            Some(ip) => client = ip,
+            None => break,
+        }
+    }
+    client
+}
@@ -0,0 +1,311 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use axum::Router;
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use socket2::{Domain, Protocol, Type};
+
+use crate::client_cert::ClientCertAcceptor;
+use crate::config::Config;
+use crate::ip_filter::IpNet;
+use crate::proxy_protocol::ProxyAcceptor;
+use crate::shutdown::{self, Stop};
+
+/// What Unix socket clients are reported as to whitelists, rate limits and the
+/// access log. They are local by definition; put 127.0.0.1 in `trusted_proxies`
+/// to take the real client IP from X-Forwarded-For when nginx sits in front.
+#[cfg(unix)]
+const UNIX_PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// First descriptor systemd passes with socket activation (sd_listen_fds(3)).
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+pub fn format_address(scope: &str, ip: &str, port: u16) -> String {
+    let scope = scope.trim().to_lowercase();
+    match scope.as_str() {
+        "localhost" | "local" => format!("127.0.0.1:{}", &port),
+        "lan" => format!("{}:{}", &ip, &port),
+        "public" | "production" | "prod" => format!("[::]:{}", &port),
+        _ => format!("127.0.0.1:{}", &port),
+    }
+}
+
+/// A parsed `[[listener]]` address.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    /// Every socket passed in via `LISTEN_FDS`, or only the Nth (from 0).
+    Systemd(Option<usize>),
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("\"unix:\" needs a socket path".to_string());
+            }
+            if !cfg!(unix) {
+                return Err(format!("\"{s}\": Unix sockets are only supported on Unix"));
+            }
+            return Ok(Address::Unix(PathBuf::from(path)));
+        }
+        if s == "systemd" || s.starts_with("systemd:") {
+            if !cfg!(unix) {
+                return Err(format!("\"{s}\": socket activation is only supported on Unix"));
+            }
+            return match s.strip_prefix("systemd:") {
+                None => Ok(Address::Systemd(None)),
+                Some(n) => n
+                    .parse()
+                    .map(|n| Address::Systemd(Some(n)))
+                    .map_err(|_| format!("\"{s}\": expected \"systemd:N\" with N a socket index")),
+            };
+        }
+        s.parse()
+            .map(Address::Tcp)
+            .map_err(|_| format!("\"{s}\" is not \"ip:port\", \"unix:/path\" or \"systemd[:N]\""))
+    }
+}
+
+pub enum Socket {
+    Tcp(std::net::TcpListener),
+    /// The path is set for sockets webify created, which it removes on exit.
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener, Option<PathBuf>),
+}
+
+pub struct Bound {
+    pub socket: Socket,
+    pub tls: bool,
+    /// Set for TCP listeners with `proxy_protocol = true`.
+    pub proxy: Option<ProxyAcceptor>,
+}
+
+/// TCP listener with SO_REUSEADDR and a 1024 backlog. `v6_only` keeps an IPv6
+/// wildcard from also claiming IPv4, so "0.0.0.0:80" and "[::]:80" can be
+/// separate listeners.
+fn bind_tcp(addr: SocketAddr, v6_only: bool) -> io::Result<std::net::TcpListener> {
+    let socket = socket2::Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(v6_only)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> io::Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+    // A socket file left behind by an unclean exit would make bind fail.
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// Take over the sockets systemd passed to this process, in order. Empty
+/// unless `LISTEN_PID` names this process.
+#[cfg(unix)]
+fn systemd_sockets() -> io::Result<Vec<Socket>> {
+    use std::os::fd::{FromRawFd, IntoRawFd};
+    let ours = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<i32>().ok())
+        .unwrap_or(0);
+    if !ours || count <= 0 {
+        return Ok(Vec::new());
+    }
+    let mut sockets = Vec::new();
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+        // SAFETY: systemd passes these descriptors to this process for it to
+        // own, and each one is taken over exactly once here.
+        let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+        // getsockname only yields a Unix address for AF_UNIX sockets.
+        let socket = if unix.local_addr().is_ok() {
+            unix.set_nonblocking(true)?;
+            Socket::Unix(unix, None)
+        } else {
+            // SAFETY: the descriptor was just released from `unix` above.
+            let tcp = unsafe { std::net::TcpListener::from_raw_fd(unix.into_raw_fd()) };
+            tcp.set_nonblocking(true)?;
+            Socket::Tcp(tcp)
+        };
+        sockets.push(socket);
+    }
+    Ok(sockets)
+}
+
+/// Bind every configured listener, or the `scope`/`port`/`ssl_port` pair when
+/// there are no `[[listener]]` entries.
+pub fn bind_all(config: &Config) -> Result<Vec<Bound>, String> {
+    if config.listeners.is_empty() {
+        let mut specs = vec![(format_address(&config.scope, &config.ip, config.port), false)];
+        if config.ssl_enabled {
+            specs.push((format_address(&config.scope, &config.ip, config.ssl_port), true));
+        }
+        return specs
+            .into_iter()
+            .map(|(addr, tls)| {
+                let parsed: SocketAddr = addr.parse().map_err(|_| format!("{addr}: invalid address"))?;
+                // "[::]" from scope = "public" stays dual-stack.
+                let socket = bind_tcp(parsed, false).map_err(|e| format!("could not bind {addr}: {e}"))?;
+                Ok(Bound { socket: Socket::Tcp(socket), tls, proxy: None })
+            })
+            .collect();
+    }
+
+    #[cfg(unix)]
+    let mut inherited: Vec<Option<Socket>> = systemd_sockets()
+        .map_err(|e| format!("could not take over systemd sockets: {e}"))?
+        .into_iter()
+        .map(Some)
+        .collect();
+    // Read once at startup like the rest of the listener settings; entries
+    // that don't parse are reported by the site builder and `webify check`.
+    let trusted: Vec<IpNet> = config.trusted_proxies.iter().filter_map(|e| e.parse().ok()).collect();
+    let mut bound = Vec::new();
+    for listener in &config.listeners {
+        let address: Address = listener.address.parse()?;
+        let sockets = match address {
+            Address::Tcp(addr) => vec![Socket::Tcp(
+                bind_tcp(addr, true).map_err(|e| format!("could not bind {addr}: {e}"))?,
+            )],
+            #[cfg(unix)]
+            Address::Unix(path) => vec![Socket::Unix(
+                bind_unix(&path).map_err(|e| format!("could not bind {}: {e}", path.display()))?,
+                Some(path),
+            )],
+            #[cfg(unix)]
+            Address::Systemd(None) => {
+                let all: Vec<Socket> = inherited.iter_mut().filter_map(Option::take).collect();
+                if all.is_empty() {
+                    return Err("\"systemd\": no sockets were passed in via LISTEN_FDS".to_string());
+                }
+                all
+            }
+            #[cfg(unix)]
+            Address::Systemd(Some(n)) => match inherited.get_mut(n).and_then(Option::take) {
+                Some(socket) => vec![socket],
+                None => return Err(format!("\"systemd:{n}\": no such socket in LISTEN_FDS, or already used")),
+            },
+            #[cfg(not(unix))]
+            _ => unreachable!("Address::from_str rejects Unix-only listeners"),
+        };
+        for socket in sockets {
+            #[cfg(unix)]
+            if listener.tls && matches!(socket, Socket::Unix(..)) {
+                return Err(format!("{}: TLS is only supported on TCP sockets", listener.address));
+            }
+            #[cfg(unix)]
+            if listener.proxy_protocol && matches!(socket, Socket::Unix(..)) {
+                return Err(format!("{}: proxy_protocol is only supported on TCP sockets", listener.address));
+            }
+            let proxy = listener.proxy_protocol.then(|| ProxyAcceptor::new(trusted.clone()));
+            bound.push(Bound { socket, tls: listener.tls, proxy });
+        }
+    }
+    Ok(bound)
+}
+
+/// axum-server handle that starts a graceful shutdown once `stop` flips. It
+/// drains on its own and force-closes whatever is left after the timeout.
+fn shutdown_handle(stop: Stop, timeout: Duration) -> axum_server::Handle {
+    let handle = axum_server::Handle::new();
+    let shutdown_handle = handle.clone();
+    tokio::spawn(async move {
+        shutdown::stopped(stop).await;
+        shutdown_handle.graceful_shutdown(Some(timeout));
+    });
+    handle
+}
+
+/// Serve `app` over plain HTTP on one socket until shutdown has drained.
+pub async fn serve(bound: Bound, app: Router, stop: Stop, timeout: Duration) -> io::Result<()> {
+    match (bound.socket, bound.proxy) {
+        // axum::serve has no hook before the first request byte, so PROXY
+        // protocol listeners go through axum-server's acceptor chain.
+        (Socket::Tcp(listener), Some(proxy)) => {
+            axum_server::from_tcp(listener)?
+                .acceptor(proxy)
+                .handle(shutdown_handle(stop, timeout))
+                .serve(app.into_make_service())
+                .await
+        }
+        (Socket::Tcp(listener), None) => {
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            let server = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(shutdown::stopped(stop.clone()));
+            shutdown::drain(server, stop, timeout).await
+        }
+        #[cfg(unix)]
+        (Socket::Unix(listener, path), _) => {
+            let listener = tokio::net::UnixListener::from_std(listener)?;
+            let app = app.layer(axum::Extension(axum::extract::ConnectInfo(UNIX_PEER)));
+            let server = axum::serve(listener, app.into_make_service())
+                .with_graceful_shutdown(shutdown::stopped(stop.clone()));
+            let result = shutdown::drain(server, stop, timeout).await;
+            if let Some(path) = path {
+                let _ = std::fs::remove_file(path);
+            }
+            result
+        }
+    }
+}
+
+/// Serve `app` over HTTPS on one TCP socket. With PROXY protocol the header is
+/// read before the TLS handshake; a verified client certificate is attached to
+/// each request after it.
+pub async fn serve_tls(
+    bound: Bound,
+    app: Router,
+    tls: RustlsConfig,
+    stop: Stop,
+    timeout: Duration,
+) -> io::Result<()> {
+    let listener = match bound.socket {
+        Socket::Tcp(listener) => listener,
+        #[cfg(unix)]
+        Socket::Unix(..) => {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "TLS is only supported on TCP sockets"));
+        }
+    };
+    let handle = shutdown_handle(stop, timeout);
+    match bound.proxy {
+        Some(proxy) => {
+            axum_server::from_tcp(listener)?
+                .acceptor(ClientCertAcceptor::new(RustlsAcceptor::new(tls).acceptor(proxy)))
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await
+        }
+        None => {
+            axum_server::from_tcp(listener)?
+                .acceptor(ClientCertAcceptor::new(RustlsAcceptor::new(tls)))
+                .handle(handle)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+        }
+    }
+}
@@ -20,14 +20,35 @@ mod admin;
 mod blog;
 mod acme;
 mod file_gate;
+mod reload;
+mod proxy;
+mod certs;
+mod ip_filter;
+mod rate_limit;
+mod access_log;
+mod metrics;
+mod shutdown;
+mod site_headers;
+mod hosts;
+mod listeners;
+mod proxy_protocol;
+mod client_cert;
+mod templating;
+mod template_functions;
+mod forum_sessions;
+mod migrations;
+mod manage;
+mod backup;
+mod paths;
+mod check;
 
 use crate::config::read_config;
 use crate::generate::*;
 use crate::help::print_help;
 use crate::out::setup;
-use crate::routes::app;
+use crate::routes::{app, build_sites};
+use crate::listeners::format_address;
 use crate::forum::{init_db, ForumDb};
-use crate::interaction::Room;
 
 use axum_server::tls_rustls::RustlsConfig;
 use axum::{
@@ -37,7 +58,6 @@ use axum::{
     response::{IntoResponse, Redirect, Response},
     http::{header::HOST, StatusCode},
 };
-use tera::Tera;
 use solarized::{
     BLUE, CYAN, GREEN, MAGENTA, ORANGE, RED, VIOLET, YELLOW,
     PrintMode::NewLine,
@@ -46,35 +66,9 @@ use solarized::{
 };
 use std::env;
 use std::sync::Arc;
-use std::net::SocketAddr;
+use std::time::Duration;
 use webbrowser;
 use rustls::crypto::ring;
-use tokio::sync::broadcast;
-use chrono::Utc;
-use std::collections::HashMap;
-
-async fn bind_with_backlog(addr: &str) -> std::io::Result<tokio::net::TcpListener> {
-    let addr: std::net::SocketAddr = addr
-        .parse()
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
-    let socket = match addr {
-        std::net::SocketAddr::V4(_) => tokio::net::TcpSocket::new_v4()?,
-        std::net::SocketAddr::V6(_) => tokio::net::TcpSocket::new_v6()?,
-    };
-    socket.set_reuseaddr(true)?;
-    socket.bind(addr)?;
-    socket.listen(1024)
-}
-
-fn format_address(scope: &str, ip: &str, port: u16) -> String {
-    let scope = scope.trim().to_lowercase();
-    match scope.as_str() {
-        "localhost" | "local" => format!("127.0.0.1:{}", &port),
-        "lan" => format!("{}:{}", &ip, &port),
-        "public" | "production" | "prod" => format!("[::]:{}", &port),
-        _ => format!("127.0.0.1:{}", &port),
-    }
-}
 
 const ACME_CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
 
@@ -116,22 +110,51 @@ pub struct AppState {
     pub forum_db: crate::forum::ForumDb,
     pub access_rules: Arc<tokio::sync::RwLock<Vec<crate::auth_guard::AccessRule>>>,
     pub db_file_guards: Arc<tokio::sync::RwLock<Vec<crate::file_gate::DbFileGuard>>>,
-    pub tera: Tera,
-    pub interaction: crate::interaction::InteractionState,
-    pub stream: crate::stream::StreamState,
+    pub tera: Arc<crate::templating::Templates>,
+    pub interaction: Arc<crate::interaction::InteractionState>,
+    pub stream: Arc<crate::stream::StreamState>,
     pub acme_challenges: crate::acme::ChallengeStore,
+    pub rate_limiter: Arc<crate::rate_limit::RateLimiter>,
+    pub metrics: Arc<crate::metrics::Metrics>,
 }
 
 #[tokio::main]
 async fn main() {
     // console_subscriber::init();
     let _ = ring::default_provider().install_default();
-    clear();
-    let args: Vec<String> = env::args().collect();
+    let args = crate::paths::take_config_flag(env::args().collect());
+    // Neither needs the config to parse: help prints no paths, and check
+    // reports a broken config itself.
     if args.contains(&"-h".to_string()) || args.contains(&"--help".to_string()) {
+        clear();
         print_help(args[0].clone());
         return;
     };
+    if args.get(1).map(String::as_str) == Some("check") {
+        std::process::exit(crate::check::run());
+    }
+    // Every command, the server included, refuses a config it can't parse
+    // rather than falling back to default paths.
+    if let Err(e) = crate::paths::init() {
+        print_colored(
+            &["Could not load ", &crate::paths::config_file().display().to_string(), ": ", &e],
+            &[ORANGE, RED, ORANGE, RED],
+            NewLine,
+        );
+        std::process::exit(1);
+    }
+    if args.get(1).map(String::as_str) == Some("db") {
+        std::process::exit(crate::migrations::cli(&args[2..]).await);
+    }
+    if let Some(command @ ("user" | "editor" | "session")) = args.get(1).map(String::as_str) {
+        std::process::exit(crate::manage::run(command, &args[2..]).await);
+    }
+    match args.get(1).map(String::as_str) {
+        Some("backup") => std::process::exit(crate::backup::backup_cli(&args[2..]).await),
+        Some("restore") => std::process::exit(crate::backup::restore_cli(&args[2..]).await),
+        _ => {}
+    }
+    clear();
     print_colored(
         &["R", "a", "i", "n", "b", "o", "w", "s"],
         &[VIOLET, BLUE, CYAN, GREEN, YELLOW, ORANGE, RED, MAGENTA],
@@ -161,16 +184,16 @@ async fn main() {
                 }
             });
         }
-        let mut tera = match Tera::new("static/**/*.html") {
+        let tera = match crate::utils::load_templates() {
             Ok(t) => t,
             Err(e) => {
                 println!("Tera parsing error: {}", e);
                 std::process::exit(1);
             }
         };
-        tera.autoescape_on(vec![]);
         let config_arc = Arc::new(config);
         let forum_db: ForumDb = init_db().await;
+        crate::backup::hold_server_lock();
         let access_rules = Arc::new(tokio::sync::RwLock::new(
             crate::auth_guard::load_access_rules(&forum_db).await,
         ));
@@ -180,99 +203,129 @@ async fn main() {
         let mut interaction = crate::interaction::InteractionState::new();
         crate::commands::register_all(&mut interaction);
         if let Some(permanent_rooms) = &config_arc.permanent_rooms {
-            let mut rooms = interaction.rooms.write().await;
-            for room_config in permanent_rooms {
-                // Generate a simple ID from the name (slugify)
-                let room_id = room_config.name.trim().to_lowercase().replace(" ", "-");
-                let (tx, _rx) = broadcast::channel(100);
-                
-                let room = Room {
-                    id: room_id.clone(),
-                    label: room_config.name.clone(),
-                    tx,
-                    created_at: Utc::now(),
-                    users: HashMap::new(),
-                    max_controllers: room_config.max_controllers,
-                    max_doers: room_config.max_doers,
-                    current_color: "#808080".to_string(),
-                    password: room_config.password.clone(),
-                };
-                
-                rooms.insert(room_id, room);
-            }
+            interaction.ensure_permanent_rooms(permanent_rooms).await;
         }
         let forum_config = Arc::new(crate::forum::read_forum_config());
         crate::forum::seed_categories(&forum_db, &forum_config).await;
-        let stream = crate::stream::StreamState::new(config_arc.public_ip.clone()).expect("Failed to initialize stream state");
+        let stream = Arc::new(crate::stream::StreamState::new(config_arc.public_ip.clone()).expect("Failed to initialize stream state"));
+        let template_functions = crate::template_functions::TemplateFunctions::new(forum_db.clone(), stream.clone());
         let state = Arc::new(AppState {
             config: config_arc.clone(),
             forum_config,
             forum_db,
             access_rules,
             db_file_guards,
-            tera,
-            interaction,
+            tera: Arc::new(crate::templating::Templates::new(tera, template_functions, config_arc.dev_mode)),
+            interaction: Arc::new(interaction),
             stream,
             acme_challenges: crate::acme::new_store(),
+            rate_limiter: Arc::new(crate::rate_limit::RateLimiter::default()),
+            metrics: Arc::new(crate::metrics::Metrics::default()),
         });
-        let app = app(state.clone()).await;
-        if state.config.ssl_enabled {
-            // Two-listener model: plain HTTP on `port`, HTTPS on `ssl_port`. They
-            // can't share a socket without the old dual-protocol sniffing, so the
-            // ports must differ (route external :80 -> port and :443 -> ssl_port).
-            if state.config.port == state.config.ssl_port {
+        tokio::spawn(crate::rate_limit::eviction_loop(state.rate_limiter.clone()));
+        if state.config.backup_dir.is_some() {
+            tokio::spawn(crate::backup::schedule(state.clone()));
+        }
+        let sites = Arc::new(tokio::sync::RwLock::new(Arc::new(build_sites(state.clone()).await)));
+        tokio::spawn(crate::reload::watch(sites.clone()));
+        if state.config.dev_mode {
+            tokio::spawn(crate::templating::watch(state.tera.clone()));
+        }
+        let app = app(sites).await;
+        let stop = crate::shutdown::listen(state.clone());
+        let drain_timeout = Duration::from_secs(state.config.shutdown_timeout);
+        // Without [[listener]] entries: plain HTTP on `port`, HTTPS on `ssl_port`.
+        // They can't share a socket without the old dual-protocol sniffing, so
+        // the ports must differ (route external :80 -> port and :443 -> ssl_port).
+        if state.config.ssl_enabled
+            && state.config.listeners.is_empty()
+            && state.config.port == state.config.ssl_port
+        {
+            print_colored(
+                &["ssl_enabled requires port and ssl_port to differ (map :80->port, :443->ssl_port)"],
+                &[RED],
+                NewLine,
+            );
+            std::process::exit(1);
+        }
+        let bound = match crate::listeners::bind_all(&state.config) {
+            Ok(bound) => bound,
+            Err(e) => {
+                print_colored(&["Failed to bind listeners: ", &e], &[ORANGE, RED], NewLine);
+                std::process::exit(1);
+            }
+        };
+        let (tls_sockets, plain_sockets): (Vec<_>, Vec<_>) = bound.into_iter().partition(|b| b.tls);
+        if !tls_sockets.is_empty() && !state.config.ssl_enabled {
+            print_colored(
+                &["tls listeners require ssl_enabled and a certificate"],
+                &[RED],
+                NewLine,
+            );
+            std::process::exit(1);
+        }
+
+        // Start the plain-HTTP listeners first so they can answer the ACME
+        // HTTP-01 challenge during first issuance below.
+        let http_app = if state.config.ssl_enabled {
+            make_http_app(&state.config.http_mode, app.clone())
+        } else {
+            app.clone()
+        };
+        let mut servers = Vec::new();
+        for bound in plain_sockets {
+            servers.push(tokio::spawn(crate::listeners::serve(
+                bound,
+                http_app.clone(),
+                stop.clone(),
+                drain_timeout,
+            )));
+        }
+
+        if !tls_sockets.is_empty() {
+            let cert_groups = crate::certs::cert_groups(&state.config);
+            if cert_groups.is_empty() {
                 print_colored(
-                    &["ssl_enabled requires port and ssl_port to differ (map :80->port, :443->ssl_port)"],
+                    &["ssl_enabled requires ssl_cert_path/ssl_key_path or at least one [[certificate]]"],
                     &[RED],
                     NewLine,
                 );
                 std::process::exit(1);
             }
-            let http_addr = format_address(
-                state.config.scope.as_str(),
-                state.config.ip.as_str(),
-                state.config.port,
-            );
-            let ssladdr = format_address(
-                state.config.scope.as_str(),
-                state.config.ip.as_str(),
-                state.config.ssl_port,
-            );
-
-            // Start the HTTP listener first so it can answer the ACME HTTP-01
-            // challenge during first issuance below.
-            let http_app = make_http_app(&state.config.http_mode, app.clone());
-            tokio::spawn(async move {
-                let listener = bind_with_backlog(&http_addr)
-                    .await
-                    .expect("Failed to bind HTTP port");
-                axum::serve(listener, http_app.into_make_service_with_connect_info::<SocketAddr>())
-                    .await
-                    .expect("HTTP server failed");
-            });
 
-            // Obtain/renew the certificate via ACME before binding HTTPS.
-            if state.config.acme_enabled {
-                if let Err(e) = crate::acme::ensure_certificate(&state.config, &state.acme_challenges).await {
-                    print_colored(
-                        &["ACME certificate request failed: ", &e.to_string()],
-                        &[ORANGE, RED],
-                        NewLine,
-                    );
+            // HTTP-01 orders can be answered by the HTTP listener right away.
+            // TLS-ALPN-01 is answered by the HTTPS listener itself, so those
+            // orders run from renewal_loop once it is bound.
+            let tls_alpn = state.config.acme_challenge == "tls-alpn-01";
+            let resolver = Arc::new(crate::certs::CertResolver::default());
+            if !tls_alpn {
+                for group in cert_groups.iter().filter(|g| g.acme) {
+                    if let Err(e) = crate::acme::ensure_certificate(&state.config, group, &state.acme_challenges, &resolver).await {
+                        print_colored(
+                            &["ACME certificate request failed: ", &e.to_string()],
+                            &[ORANGE, RED],
+                            NewLine,
+                        );
+                    }
                 }
             }
 
-            let cert = state.config.ssl_cert_path.clone().expect("SSL cert path is required");
-            let key = state.config.ssl_key_path.clone().expect("SSL key path is required");
-            let rustls_config = match crate::utils::build_tls_config(&cert, &key).await {
-                Ok(c) => RustlsConfig::from_config(c),
-                Err(e) => {
+            for (i, group) in cert_groups.iter().enumerate() {
+                if let Err(e) = resolver.load(group, i == 0).await {
+                    if tls_alpn && group.acme {
+                        print_colored(
+                            &["No certificate yet at ", &group.cert_path, "; requesting one via TLS-ALPN-01 once HTTPS is up"],
+                            &[ORANGE, VIOLET, ORANGE],
+                            NewLine,
+                        );
+                        continue;
+                    }
                     print_colored(
                         &[
                             "Failed to load TLS certificate from ",
-                            &cert,
+                            &group.cert_path,
                             " / ",
-                            &key,
+                            &group.key_path,
                             ": ",
                             &e.to_string(),
                         ],
@@ -281,38 +334,55 @@ async fn main() {
                     );
                     std::process::exit(1);
                 }
+            }
+            let client_verifier = match &state.config.client_ca_path {
+                Some(path) => match crate::client_cert::client_verifier(&crate::paths::config_path(path)) {
+                    Ok(verifier) => Some(verifier),
+                    Err(e) => {
+                        print_colored(&["Failed to load client_ca_path: ", &e], &[ORANGE, RED], NewLine);
+                        std::process::exit(1);
+                    }
+                },
+                None => None,
             };
+            let rustls_config = RustlsConfig::from_config(crate::utils::build_tls_config(resolver.clone(), client_verifier));
 
-            if state.config.acme_enabled {
+            if cert_groups.iter().any(|g| g.acme) {
                 let cfg = state.config.clone();
                 let store = state.acme_challenges.clone();
-                let rc = rustls_config.clone();
                 tokio::spawn(async move {
-                    crate::acme::renewal_loop(cfg, store, rc).await;
+                    crate::acme::renewal_loop(cfg, store, resolver).await;
                 });
             }
 
-            let ssl_listener = bind_with_backlog(&ssladdr)
-                .await
-                .expect("Failed to bind HTTPS port")
-                .into_std()
-                .expect("Failed to convert HTTPS listener");
-            axum_server::tls_rustls::from_tcp_rustls(ssl_listener, rustls_config)
-                .expect("Failed to create HTTPS server")
-                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
-                .await
-                .expect("HTTPS server failed");
-        } else {
-            let addr = format_address(
-                state.config.scope.as_str(),
-                state.config.ip.as_str(),
-                state.config.port
-            );
-            let listener = bind_with_backlog(&addr).await.expect("Failed to bind HTTP port");
-            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
-                .await
-                .expect("HTTP server failed");
+            let https_app = app.layer(axum::Extension(crate::proxy::Https));
+            for bound in tls_sockets {
+                servers.push(tokio::spawn(crate::listeners::serve_tls(
+                    bound,
+                    https_app.clone(),
+                    rustls_config.clone(),
+                    stop.clone(),
+                    drain_timeout,
+                )));
+            }
+        }
+        for server in servers {
+            let result = match server.await {
+                Ok(result) => result.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = result {
+                print_colored(&["Server failed: ", &e], &[ORANGE, RED], NewLine);
+            }
         }
+    } else if crate::paths::config_from_flag() {
+        // An explicitly named config is never replaced by a scaffold in the cwd.
+        print_colored(
+            &["Could not load ", &crate::paths::get().config.display().to_string()],
+            &[ORANGE, RED],
+            NewLine,
+        );
+        std::process::exit(1);
     } else {
         generate_files();
     }
@@ -0,0 +1,300 @@
+use std::io::{self, BufRead, Write};
+
+use bcrypt::{hash, DEFAULT_COST};
+use chrono::Utc;
+use solarized::{
+    print_fancy,
+    BLUE, CYAN, GREEN, ORANGE, RED, VIOLET,
+    BOLD,
+    PrintMode::NewLine,
+};
+use uuid::Uuid;
+
+use crate::forum::{init_db, ForumDb, Role};
+use crate::forum_sessions;
+
+const USAGE: &[&str] = &[
+    "webify user list",
+    "webify user create <username> <email> [--role Member|Admin|Owner]",
+    "webify user set-role <username> <Member|Admin|Owner>",
+    "webify user ban <username>",
+    "webify user unban <username>",
+    "webify user reset-password <username>",
+    "webify user verify <username>",
+    "webify editor add <email>",
+    "webify editor remove <email>",
+    "webify session purge [--expired]",
+];
+
+fn log_ok(msg: &str) {
+    print_fancy(&[("[manage] ", BLUE, vec![]), (msg, GREEN, vec![BOLD])], NewLine);
+}
+
+fn log_err(msg: &str) {
+    print_fancy(&[("[manage] ", BLUE, vec![]), (msg, RED, vec![BOLD])], NewLine);
+}
+
+fn usage() -> i32 {
+    log_err("usage:");
+    for line in USAGE {
+        print_fancy(&[("  ", CYAN, vec![]), (line, VIOLET, vec![])], NewLine);
+    }
+    2
+}
+
+/// Unlike the web form, an unrecognised role is an error here rather than
+/// quietly becoming Member.
+fn parse_role(s: &str) -> Option<Role> {
+    match s {
+        "Member" | "member" => Some(Role::Member),
+        "Admin" | "admin" => Some(Role::Admin),
+        "Owner" | "owner" => Some(Role::Owner),
+        _ => None,
+    }
+}
+
+/// A password is read as one line of stdin rather than taken from argv, so it
+/// stays out of shell history and the process list.
+fn read_password() -> Result<String, String> {
+    print!("Password: ");
+    let _ = io::stdout().flush();
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line).map_err(|e| e.to_string())?;
+    let password = line.trim_end_matches(['\r', '\n']).to_string();
+    if password.is_empty() {
+        return Err("password must not be empty".to_string());
+    }
+    Ok(password)
+}
+
+async fn user_exists(db: &ForumDb, username: &str) -> bool {
+    sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_one(&**db)
+        .await
+        .unwrap_or(0)
+        > 0
+}
+
+/// Fail when an UPDATE matched no user.
+fn one_user(result: Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error>, username: &str) -> Result<(), String> {
+    match result {
+        Ok(r) if r.rows_affected() == 0 => Err(format!("no user named \"{username}\"")),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+async fn user_list(db: &ForumDb) -> Result<(), String> {
+    let rows: Vec<(String, String, String, bool, bool)> = sqlx::query_as(
+        "SELECT username, email, role, is_verified, is_banned FROM users ORDER BY username",
+    )
+    .fetch_all(&**db)
+    .await
+    .map_err(|e| e.to_string())?;
+    if rows.is_empty() {
+        print_fancy(&[("no users", CYAN, vec![])], NewLine);
+    }
+    for (username, email, role, verified, banned) in rows {
+        let mut flags = Vec::new();
+        if !verified {
+            flags.push("unverified");
+        }
+        if banned {
+            flags.push("banned");
+        }
+        print_fancy(&[
+            (&username, VIOLET, vec![BOLD]),
+            (&format!("  {email}  "), CYAN, vec![]),
+            (&role, GREEN, vec![]),
+            (&format!("  {}", flags.join(", ")), ORANGE, vec![]),
+        ], NewLine);
+    }
+    Ok(())
+}
+
+async fn user_create(db: &ForumDb, args: &[String]) -> Result<(), String> {
+    let (Some(username), Some(email)) = (args.first(), args.get(1)) else {
+        return Err("expected <username> <email>".to_string());
+    };
+    let role = match args.iter().position(|a| a == "--role") {
+        Some(i) => {
+            let name = args.get(i + 1).ok_or("--role needs a value")?;
+            parse_role(name).ok_or_else(|| format!("unknown role \"{name}\""))?
+        }
+        None => Role::Member,
+    };
+    if user_exists(db, username).await {
+        return Err(format!("user \"{username}\" already exists"));
+    }
+    let password = read_password()?;
+    let hashed = hash(password, DEFAULT_COST).map_err(|e| e.to_string())?;
+    // Accounts created by the operator are trusted, so they skip email verification.
+    sqlx::query(
+        "INSERT INTO users (username, email, password_hash, is_verified, verification_token, role, is_banned) VALUES (?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(username)
+    .bind(email)
+    .bind(&hashed)
+    .bind(true)
+    .bind(Uuid::new_v4().to_string())
+    .bind(role.to_string())
+    .bind(false)
+    .execute(&**db)
+    .await
+    .map_err(|e| e.to_string())?;
+    log_ok(&format!("created {username} ({role})"));
+    Ok(())
+}
+
+async fn user_command(db: &ForumDb, args: &[String]) -> Result<(), String> {
+    let sub = args.first().map(String::as_str).unwrap_or("");
+    if sub == "list" {
+        return user_list(db).await;
+    }
+    if sub == "create" {
+        return user_create(db, &args[1..]).await;
+    }
+    let username = args.get(1).ok_or("expected <username>")?;
+    match sub {
+        "set-role" => {
+            let name = args.get(2).ok_or("expected <Member|Admin|Owner>")?;
+            let role = parse_role(name).ok_or_else(|| format!("unknown role \"{name}\""))?;
+            let result = sqlx::query("UPDATE users SET role = ? WHERE username = ?")
+                .bind(role.to_string())
+                .bind(username)
+                .execute(&**db)
+                .await;
+            one_user(result, username)?;
+            log_ok(&format!("{username} is now {role}"));
+        }
+        "ban" => {
+            let result = sqlx::query("UPDATE users SET is_banned = 1 WHERE username = ?")
+                .bind(username)
+                .execute(&**db)
+                .await;
+            one_user(result, username)?;
+            forum_sessions::revoke_user(db, username).await;
+            log_ok(&format!("banned {username}"));
+        }
+        "unban" => {
+            let result = sqlx::query("UPDATE users SET is_banned = 0 WHERE username = ?")
+                .bind(username)
+                .execute(&**db)
+                .await;
+            one_user(result, username)?;
+            log_ok(&format!("unbanned {username}"));
+        }
+        "reset-password" => {
+            if !user_exists(db, username).await {
+                return Err(format!("no user named \"{username}\""));
+            }
+            let password = read_password()?;
+            let hashed = hash(password, DEFAULT_COST).map_err(|e| e.to_string())?;
+            let result = sqlx::query("UPDATE users SET password_hash = ? WHERE username = ?")
+                .bind(&hashed)
+                .bind(username)
+                .execute(&**db)
+                .await;
+            one_user(result, username)?;
+            forum_sessions::revoke_user(db, username).await;
+            log_ok(&format!("password reset for {username}; existing sessions signed out"));
+        }
+        "verify" => {
+            let result = sqlx::query("UPDATE users SET is_verified = 1 WHERE username = ?")
+                .bind(username)
+                .execute(&**db)
+                .await;
+            one_user(result, username)?;
+            log_ok(&format!("verified {username}"));
+        }
+        _ => return Err(format!("unknown user command \"{sub}\"")),
+    }
+    Ok(())
+}
+
+async fn editor_command(db: &ForumDb, args: &[String]) -> Result<(), String> {
+    let email = args
+        .get(1)
+        .map(|e| e.trim().to_lowercase())
+        .ok_or("expected <email>")?;
+    match args.first().map(String::as_str) {
+        Some("add") => {
+            if !email.contains('@') {
+                return Err(format!("\"{email}\" is not an email address"));
+            }
+            sqlx::query(
+                "INSERT OR IGNORE INTO dashboard_editors (email, granted_by, granted_at) VALUES (?, ?, ?)",
+            )
+            .bind(&email)
+            .bind("webify editor add")
+            .bind(Utc::now().to_rfc3339())
+            .execute(&**db)
+            .await
+            .map_err(|e| e.to_string())?;
+            log_ok(&format!("{email} can now edit the dashboard"));
+        }
+        Some("remove") => {
+            let result = sqlx::query("DELETE FROM dashboard_editors WHERE email = ?")
+                .bind(&email)
+                .execute(&**db)
+                .await
+                .map_err(|e| e.to_string())?;
+            if result.rows_affected() == 0 {
+                return Err(format!("{email} is not an editor"));
+            }
+            log_ok(&format!("removed editor {email}"));
+        }
+        _ => return Err("expected add or remove".to_string()),
+    }
+    Ok(())
+}
+
+/// Delete auth guard, file gate and forum sessions: every one, or with
+/// `--expired` only those past their expiry.
+async fn session_command(db: &ForumDb, args: &[String]) -> Result<(), String> {
+    if args.first().map(String::as_str) != Some("purge") {
+        return Err("expected purge".to_string());
+    }
+    let expired_only = args.iter().any(|a| a == "--expired");
+    let now = Utc::now().to_rfc3339();
+    for table in ["guard_sessions", "file_gate_sessions", "forum_sessions"] {
+        let sql = if expired_only {
+            format!("DELETE FROM {table} WHERE expires_at < ?")
+        } else {
+            format!("DELETE FROM {table}")
+        };
+        let mut query = sqlx::query(&sql);
+        if expired_only {
+            query = query.bind(&now);
+        }
+        let result = query
+            .execute(&**db)
+            .await
+            .map_err(|e| format!("{table}: {e}"))?;
+        log_ok(&format!("{table}: removed {}", result.rows_affected()));
+    }
+    Ok(())
+}
+
+/// `webify user|editor|session ...`: maintenance commands that work on
+/// forum.db directly, with the server stopped or running.
+pub async fn run(command: &str, args: &[String]) -> i32 {
+    if args.is_empty() {
+        return usage();
+    }
+    let db = init_db().await;
+    let result = match command {
+        "user" => user_command(&db, args).await,
+        "editor" => editor_command(&db, args).await,
+        "session" => session_command(&db, args).await,
+        _ => return usage(),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            log_err(&e);
+            1
+        }
+    }
+}
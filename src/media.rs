@@ -1,16 +1,17 @@
-use axum::response::Html;
-use tera::{Tera, Context};
+use axum::response::{Html, IntoResponse, Response};
+use tera::Context;
+use crate::templating::Templates;
 use crate::utils::read_media_files;
 use rand::{seq::SliceRandom, rng};
 use solarized::{print_fancy, RED, BOLD, PrintMode::NewLine};
 
 pub async fn render_html_with_media(
-    tera: &Tera,
+    tera: &Templates,
     file_path: &str,
     media_dir: &str,
     media_route: &str,
     sort_method: Option<&str>
-) -> Html<String> {
+) -> Response {
     let mut media_files = match read_media_files(media_dir).await {
         Ok(files) => files,
         Err(_) => {
@@ -18,7 +19,7 @@ pub async fn render_html_with_media(
                 ("Error: Unable to read media directory: ", RED, vec![]),
                 (media_dir, RED, vec![BOLD]),
             ], NewLine);
-            return Html("<h1>Error reading media directory</h1>".to_string());
+            return Html("<h1>Error reading media directory</h1>".to_string()).into_response();
         }
     };
     match sort_method {
@@ -38,10 +39,7 @@ pub async fn render_html_with_media(
     context.insert("media_dir", &media_dir);
     let template_name = file_path.trim_start_matches("static/");
     match tera.render(template_name, &context) {
-        Ok(rendered) => Html(rendered),
-        Err(e) => {
-            eprintln!("Tera render error: {}", e);
-            return Html("<h1>Internal Server Error</h1>".to_string())
-        }
+        Ok(rendered) => Html(rendered).into_response(),
+        Err(e) => tera.error_response(template_name, &e),
     }
 }
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+
+use crate::AppState;
+use crate::certs::cert_groups;
+use crate::config::AdminDashboard;
+use crate::routes::SharedSites;
+
+/// Upper bounds (seconds) of the request latency histogram; Prometheus' defaults.
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Default)]
+struct RequestStats {
+    count: u64,
+    sum: f64,
+    buckets: [u64; LATENCY_BUCKETS.len()],
+}
+
+/// Process-wide counters behind `/metrics`. Lives in `AppState` so totals
+/// survive config hot-reloads; gauges are read from live state at scrape time.
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<HashMap<(String, String, u16), RequestStats>>,
+    pub upload_bytes: AtomicU64,
+}
+
+impl Metrics {
+    fn observe(&self, host: String, route: String, status: u16, seconds: f64) {
+        let mut requests = self.requests.lock().unwrap();
+        let stats = requests.entry((host, route, status)).or_default();
+        stats.count += 1;
+        stats.sum += seconds;
+        for (bucket, bound) in stats.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Prefixes every site router serves besides its configured routes (see
+/// `build_sites`), plus the ACME challenge path on the outer router.
+const BUILTIN_PREFIXES: &[&str] = &[
+    "/thumbnail", "/blog", "/static", "/templates", "/uploads", "/upload", "/favicon.ico",
+    "/css", "/styles", "/js", "/scripts", "/images", "/auth", "/interaction", "/ws",
+    "/metrics", "/.well-known/acme-challenge",
+];
+
+/// Route label for a request: the longest configured route, dashboard or
+/// built-in prefix covering the path. Everything else shares "unmatched"
+/// whatever its status, so scanners can't blow up the series count with
+/// refused or redirected requests either.
+fn route_label(routes: Option<&Vec<(String, Vec<String>)>>, dashboards: &[AdminDashboard], path: &str) -> String {
+    let configured = routes.into_iter().flatten().map(|(route, _)| route.as_str());
+    let dashboards = dashboards.iter().map(|d| d.path.as_str());
+    let known = configured
+        .chain(dashboards)
+        .chain(BUILTIN_PREFIXES.iter().copied())
+        .map(|route| route.trim_end_matches('/'))
+        .filter(|route| route.is_empty() || path == *route || path.starts_with(&format!("{route}/")))
+        .max_by_key(|route| route.len());
+    match known {
+        Some("") => "/".to_string(),
+        Some(route) => route.to_string(),
+        None => "unmatched".to_string(),
+    }
+}
+
+/// Outer-router middleware counting every request by host, route and status.
+pub async fn track(State(sites): State<SharedSites>, req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let hostname = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split(':').next())
+        .unwrap_or("")
+        .to_string();
+    let path = req.uri().path().to_string();
+
+    let response = next.run(req).await;
+
+    let state = sites.read().await.state.clone();
+    if !state.config.metrics {
+        return response;
+    }
+    // Series are per configured site (wildcards included); anything else is "default".
+    let host = crate::hosts::find(&state.config.sites, &hostname)
+        .map(|(site, _)| site)
+        .unwrap_or("default")
+        .to_string();
+    let route = route_label(state.config.sites.get(&host), &state.config.admin_dashboards, &path);
+    state.metrics.observe(host, route, response.status().as_u16(), start.elapsed().as_secs_f64());
+    response
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}");
+}
+
+/// Render everything in the Prometheus text exposition format.
+async fn render(state: &AppState) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# HELP webify_http_requests_total Requests served, by host, route and status.");
+    let _ = writeln!(out, "# TYPE webify_http_requests_total counter");
+    let requests: Vec<_> = {
+        let requests = state.metrics.requests.lock().unwrap();
+        let mut rows: Vec<_> = requests
+            .iter()
+            .map(|(key, stats)| (key.clone(), stats.count, stats.sum, stats.buckets))
+            .collect();
+        rows.sort_by(|a, b| a.0.cmp(&b.0));
+        rows
+    };
+    for ((host, route, status), count, _, _) in &requests {
+        let _ = writeln!(
+            out,
+            "webify_http_requests_total{{host=\"{}\",route=\"{}\",status=\"{status}\"}} {count}",
+            escape(host),
+            escape(route),
+        );
+    }
+    let _ = writeln!(out, "# HELP webify_http_request_duration_seconds Request latency, by host, route and status.");
+    let _ = writeln!(out, "# TYPE webify_http_request_duration_seconds histogram");
+    for ((host, route, status), count, sum, buckets) in &requests {
+        let labels = format!("host=\"{}\",route=\"{}\",status=\"{status}\"", escape(host), escape(route));
+        for (bound, n) in LATENCY_BUCKETS.iter().zip(buckets) {
+            let _ = writeln!(out, "webify_http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {n}");
+        }
+        let _ = writeln!(out, "webify_http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "webify_http_request_duration_seconds_sum{{{labels}}} {sum}");
+        let _ = writeln!(out, "webify_http_request_duration_seconds_count{{{labels}}} {count}");
+    }
+
+    let (rooms, connections) = {
+        let rooms = state.interaction.rooms.read().await;
+        (rooms.len(), rooms.values().map(|r| r.users.len()).sum::<usize>())
+    };
+    gauge(&mut out, "webify_interaction_rooms", "Open interaction rooms.", rooms);
+    gauge(&mut out, "webify_websocket_connections", "Active interaction WebSocket connections.", connections);
+
+    gauge(&mut out, "webify_stream_live", "1 while a WHIP broadcast is live.", state.stream.is_live.load(Ordering::Relaxed) as u8);
+    gauge(&mut out, "webify_stream_viewers", "Connected WHEP viewers.", state.stream.viewer_count().await);
+
+    let _ = writeln!(out, "# HELP webify_upload_bytes_total Bytes written through /upload.");
+    let _ = writeln!(out, "# TYPE webify_upload_bytes_total counter");
+    let _ = writeln!(out, "webify_upload_bytes_total {}", state.metrics.upload_bytes.load(Ordering::Relaxed));
+
+    let pool = &state.forum_db;
+    let size = pool.size();
+    let idle = pool.num_idle() as u32;
+    gauge(&mut out, "webify_db_pool_connections", "Open SQLite pool connections.", size);
+    gauge(&mut out, "webify_db_pool_idle", "Idle SQLite pool connections.", idle);
+    gauge(&mut out, "webify_db_pool_in_use", "SQLite pool connections checked out.", size.saturating_sub(idle));
+    gauge(&mut out, "webify_db_pool_max", "SQLite pool connection limit.", pool.options().get_max_connections());
+
+    if state.config.ssl_enabled {
+        let _ = writeln!(out, "# HELP webify_certificate_expiry_days Days until each configured certificate expires.");
+        let _ = writeln!(out, "# TYPE webify_certificate_expiry_days gauge");
+        let now = Utc::now().timestamp();
+        for group in cert_groups(&state.config) {
+            let Ok(pem) = tokio::fs::read(&group.cert_path).await else { continue };
+            let Some(not_after) = crate::acme::parse_not_after(&pem) else { continue };
+            let _ = writeln!(
+                out,
+                "webify_certificate_expiry_days{{cert=\"{}\",domains=\"{}\"}} {:.2}",
+                escape(&group.cert_path),
+                escape(&group.domains.join(",")),
+                (not_after - now) as f64 / 86_400.0,
+            );
+        }
+    }
+    out
+}
+
+/// `GET /metrics`. Mounted only when `metrics = true`; the fallback's
+/// whitelist and auth guard checks apply to it like to any other path.
+pub async fn metrics_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        render(&state).await,
+    )
+}
@@ -0,0 +1,308 @@
+use sqlx::SqlitePool;
+use sqlx::sqlite::SqliteConnectOptions;
+use solarized::{
+    print_fancy,
+    BLUE, CYAN, GREEN, ORANGE, RED, VIOLET,
+    BOLD,
+    PrintMode::NewLine,
+};
+
+/// One numbered schema change. Versions are applied in order and recorded in
+/// `schema_version`; a released migration is never edited, a new one is
+/// appended instead.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub statements: &'static [&'static str],
+}
+
+pub static MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "baseline",
+        // `IF NOT EXISTS` so databases created before versioning adopt this
+        // as their starting point without changes.
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS categories (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                description TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS users (
+                username TEXT PRIMARY KEY,
+                email TEXT NOT NULL,
+                password_hash TEXT NOT NULL,
+                is_verified INTEGER NOT NULL DEFAULT 0,
+                verification_token TEXT NOT NULL,
+                role TEXT NOT NULL DEFAULT 'Member',
+                is_banned INTEGER NOT NULL DEFAULT 0
+            )",
+            "CREATE TABLE IF NOT EXISTS posts (
+                id TEXT PRIMARY KEY,
+                category_id TEXT NOT NULL,
+                title TEXT NOT NULL,
+                content TEXT NOT NULL,
+                author TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                updated_at TEXT,
+                is_locked INTEGER NOT NULL DEFAULT 0,
+                min_reply_role TEXT NOT NULL DEFAULT 'Member',
+                view_count INTEGER NOT NULL DEFAULT 0
+            )",
+            "CREATE TABLE IF NOT EXISTS replies (
+                id TEXT PRIMARY KEY,
+                thread_id TEXT NOT NULL,
+                content TEXT NOT NULL,
+                author TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS stream_keys (
+                username TEXT PRIMARY KEY,
+                stream_key TEXT UNIQUE NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS guard_sessions (
+                token TEXT PRIMARY KEY,
+                email TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                expires_at TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS access_rules (
+                id TEXT PRIMARY KEY,
+                domain TEXT NOT NULL DEFAULT '',
+                path TEXT NOT NULL,
+                email TEXT,
+                email_domain TEXT,
+                created_by TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS dashboard_editors (
+                email TEXT PRIMARY KEY,
+                granted_by TEXT NOT NULL,
+                granted_at TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS file_gate_sessions (
+                token TEXT PRIMARY KEY,
+                gate_hash TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                expires_at TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS db_file_guards (
+                id TEXT PRIMARY KEY,
+                label TEXT NOT NULL,
+                paths TEXT NOT NULL,
+                hash TEXT NOT NULL,
+                created_by TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+        ],
+    },
+    Migration {
+        version: 2,
+        name: "forum_sessions",
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS forum_sessions (
+                id TEXT PRIMARY KEY,
+                token_hash TEXT UNIQUE NOT NULL,
+                username TEXT NOT NULL,
+                user_agent TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                last_seen TEXT NOT NULL,
+                expires_at TEXT NOT NULL
+            )",
+        ],
+    },
+];
+
+/// The schema version this binary writes.
+pub fn latest() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+fn log_info(msg: &str) {
+    print_fancy(&[("[db] ", BLUE, vec![]), (msg, CYAN, vec![])], NewLine);
+}
+
+fn log_ok(msg: &str) {
+    print_fancy(&[("[db] ", BLUE, vec![]), (msg, GREEN, vec![BOLD])], NewLine);
+}
+
+fn log_err(msg: &str) {
+    print_fancy(&[("[db] ", BLUE, vec![]), (msg, RED, vec![BOLD])], NewLine);
+}
+
+/// Version recorded in the database; 0 for a database that predates
+/// versioning (or is empty). Read-only, so it is safe for `--dry-run`.
+pub async fn current_version(pool: &SqlitePool) -> Result<i64, String> {
+    let has_table: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'schema_version'",
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    if has_table == 0 {
+        return Ok(0);
+    }
+    sqlx::query_scalar::<_, Option<i64>>("SELECT MAX(version) FROM schema_version")
+        .fetch_one(pool)
+        .await
+        .map(|v| v.unwrap_or(0))
+        .map_err(|e| e.to_string())
+}
+
+fn too_new(current: i64) -> String {
+    format!(
+        "database is at schema version {current} but this webify only knows up to {}; \
+         run a newer webify or restore a backup taken before the upgrade",
+        latest()
+    )
+}
+
+fn pending(current: i64) -> impl Iterator<Item = &'static Migration> {
+    MIGRATIONS.iter().filter(move |m| m.version > current)
+}
+
+/// Bring the database up to `latest()`. All pending migrations run in a single
+/// transaction, so a failure leaves the schema exactly as it was. A database
+/// written by a newer binary is refused rather than guessed at.
+pub async fn run(pool: &SqlitePool) -> Result<Vec<&'static Migration>, String> {
+    let current = current_version(pool).await?;
+    if current > latest() {
+        return Err(too_new(current));
+    }
+    let todo: Vec<&Migration> = pending(current).collect();
+    if todo.is_empty() {
+        return Ok(todo);
+    }
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TEXT NOT NULL
+        )",
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    for m in &todo {
+        for statement in m.statements {
+            sqlx::query(statement)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| format!("migration {} ({}): {e}", m.version, m.name))?;
+        }
+        sqlx::query("INSERT INTO schema_version (version, name, applied_at) VALUES (?, ?, ?)")
+            .bind(m.version)
+            .bind(m.name)
+            .bind(chrono::Utc::now().to_rfc3339())
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(todo)
+}
+
+/// Startup entry point: migrate, or explain why not and exit.
+pub async fn run_or_exit(pool: &SqlitePool) {
+    match run(pool).await {
+        Ok(applied) => {
+            for m in applied {
+                log_ok(&format!("applied migration {} ({})", m.version, m.name));
+            }
+        }
+        Err(e) => {
+            log_err(&format!("{}: {e}", crate::paths::get().database.display()));
+            log_err("refusing to start");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_pending(current: i64) {
+    for m in pending(current) {
+        print_fancy(&[
+            ("  ", CYAN, vec![]),
+            (&m.version.to_string(), VIOLET, vec![BOLD]),
+            (" ", CYAN, vec![]),
+            (m.name, ORANGE, vec![]),
+        ], NewLine);
+        for statement in m.statements {
+            let sql: Vec<&str> = statement.lines().map(str::trim).collect();
+            print_fancy(&[("      ", CYAN, vec![]), (&sql.join(" "), CYAN, vec![])], NewLine);
+        }
+    }
+}
+
+/// `webify db <command>`: `status`, or `migrate [--dry-run]`.
+pub async fn cli(args: &[String]) -> i32 {
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    match args.first().map(String::as_str) {
+        Some("migrate") | Some("status") => {}
+        _ => {
+            log_err("usage: webify db migrate [--dry-run] | webify db status");
+            return 2;
+        }
+    }
+    let status = args[0] == "status";
+    let db_path = &crate::paths::get().database;
+    let path = db_path.display();
+    if (status || dry_run) && !db_path.exists() {
+        log_info(&format!("{path} does not exist; it would be created at version {}", latest()));
+        print_pending(0);
+        return 0;
+    }
+    if !(status || dry_run)
+        && let Err(e) = crate::paths::ensure_parent(db_path)
+    {
+        log_err(&format!("{path}: {e}"));
+        return 1;
+    }
+    let opts = SqliteConnectOptions::new()
+        .filename(db_path)
+        .create_if_missing(!(status || dry_run));
+    let pool = match SqlitePool::connect_with(opts).await {
+        Ok(p) => p,
+        Err(e) => {
+            log_err(&format!("{path}: {e}"));
+            return 1;
+        }
+    };
+    let current = match current_version(&pool).await {
+        Ok(v) => v,
+        Err(e) => {
+            log_err(&format!("{path}: {e}"));
+            return 1;
+        }
+    };
+    if current > latest() {
+        log_err(&format!("{path}: {}", too_new(current)));
+        return 1;
+    }
+    log_info(&format!("{path} is at schema version {current}; this binary is at {}", latest()));
+    if status || dry_run {
+        if current == latest() {
+            log_ok("up to date");
+        } else {
+            log_info("pending migrations:");
+            print_pending(current);
+        }
+        return 0;
+    }
+    match run(&pool).await {
+        Ok(applied) if applied.is_empty() => {
+            log_ok("up to date");
+            0
+        }
+        Ok(applied) => {
+            for m in applied {
+                log_ok(&format!("applied migration {} ({})", m.version, m.name));
+            }
+            0
+        }
+        Err(e) => {
+            log_err(&format!("{path}: {e}"));
+            1
+        }
+    }
+}
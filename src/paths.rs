@@ -0,0 +1,166 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use crate::config::PathOverrides;
+
+const DEFAULT_CONFIG: &str = "config.toml";
+
+static CONFIG_FILE: OnceLock<PathBuf> = OnceLock::new();
+static PATHS: OnceLock<Paths> = OnceLock::new();
+
+/// Where webify reads and writes everything it owns. Resolved once from the
+/// config file's `data_dir` and `[paths]`, so a change there needs a restart.
+#[derive(Debug)]
+pub struct Paths {
+    pub config: PathBuf,
+    pub forum_config: PathBuf,
+    pub data_dir: PathBuf,
+    pub database: PathBuf,
+    pub uploads: PathBuf,
+    pub posts: PathBuf,
+    pub drafts: PathBuf,
+    pub blog_images: PathBuf,
+    pub thumb_cache: PathBuf,
+    /// Tera templates and the `/static` mount.
+    pub static_dir: PathBuf,
+}
+
+/// Only the keys that decide paths; everything else in the file is ignored
+/// here so a config that fails full validation still finds its database.
+#[derive(Deserialize, Default)]
+struct PathKeys {
+    #[serde(default)]
+    data_dir: Option<String>,
+    #[serde(default)]
+    paths: PathOverrides,
+}
+
+/// Take `--config <file>` (or `--config=<file>`) out of the arguments so the
+/// remaining ones keep their positions. Call before anything reads a path.
+pub fn take_config_flag(args: Vec<String>) -> Vec<String> {
+    let mut rest = Vec::with_capacity(args.len());
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        let value = if arg == "--config" {
+            iter.next()
+        } else if let Some(v) = arg.strip_prefix("--config=") {
+            Some(v.to_string())
+        } else {
+            rest.push(arg);
+            continue;
+        };
+        if let Some(v) = value {
+            let _ = CONFIG_FILE.set(PathBuf::from(v));
+        }
+    }
+    rest
+}
+
+pub fn config_file() -> &'static Path {
+    CONFIG_FILE.get().map(PathBuf::as_path).unwrap_or(Path::new(DEFAULT_CONFIG))
+}
+
+/// Whether the config file was named with `--config` rather than defaulted.
+pub fn config_from_flag() -> bool {
+    CONFIG_FILE.get().is_some()
+}
+
+/// `path` as is when absolute, otherwise under `base`.
+fn under(base: &Path, path: &str) -> PathBuf {
+    let p = Path::new(path);
+    if p.is_absolute() { p.to_path_buf() } else { base.join(p) }
+}
+
+/// Work out every path from the config file. A missing file means the
+/// defaults (a first run scaffolds one); a file that exists but can't be read
+/// or parsed is an error, since guessing would point the CLI commands at a
+/// fresh forum.db in the wrong place.
+fn resolve() -> Result<Paths, String> {
+    let config = config_file().to_path_buf();
+    let config_dir = config.parent().map(Path::to_path_buf).unwrap_or_default();
+    let keys: PathKeys = match std::fs::read_to_string(&config) {
+        Ok(contents) => toml::from_str(&contents).map_err(|e| e.to_string())?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => PathKeys::default(),
+        Err(e) => return Err(e.to_string()),
+    };
+    // A relative data_dir is taken from the config file's directory, so each
+    // instance's config can sit next to its data regardless of the cwd.
+    let data_dir = match &keys.data_dir {
+        Some(d) => under(&config_dir, d),
+        None => config_dir.clone(),
+    };
+    let o = &keys.paths;
+    let pick = |value: &Option<String>, default: PathBuf| match value {
+        Some(v) => under(&data_dir, v),
+        None => default,
+    };
+    let static_dir = pick(&o.static_dir, data_dir.join("static"));
+    Ok(Paths {
+        forum_config: config_dir.join("forum.toml"),
+        database: pick(&o.database, data_dir.join("forum.db")),
+        uploads: pick(&o.uploads, data_dir.join("uploads")),
+        posts: pick(&o.posts, static_dir.join("posts")),
+        drafts: pick(&o.drafts, data_dir.join("drafts")),
+        blog_images: pick(&o.blog_images, static_dir.join("images").join("blog")),
+        thumb_cache: pick(&o.thumb_cache, data_dir.join(".thumb_cache")),
+        static_dir,
+        data_dir,
+        config,
+    })
+}
+
+/// Create the directory `path` will be written into, if it has one.
+pub fn ensure_parent(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => std::fs::create_dir_all(dir),
+        _ => Ok(()),
+    }
+}
+
+/// Every path here comes from a UTF-8 argument or config string, so the
+/// conversion cannot fail in practice.
+pub fn as_str(path: &'static Path) -> &'static str {
+    path.to_str().unwrap_or_default()
+}
+
+/// Resolve the paths once at startup, before anything reads one.
+pub fn init() -> Result<&'static Paths, String> {
+    if let Some(paths) = PATHS.get() {
+        return Ok(paths);
+    }
+    let paths = resolve()?;
+    Ok(PATHS.get_or_init(|| paths))
+}
+
+pub fn get() -> &'static Paths {
+    PATHS.get().expect("paths::init runs before anything reads a path")
+}
+
+/// A path named in config.toml (a certificate, route directory, log file and
+/// so on) as it is found on disk: relative ones are under `data_dir`, so
+/// nothing the server reads or writes depends on the working directory.
+pub fn config_path(path: &str) -> String {
+    get().in_data_dir(path).to_string_lossy().into_owned()
+}
+
+impl Paths {
+    /// A configured path: absolute as given, relative ones under `data_dir`.
+    pub fn in_data_dir(&self, path: &str) -> PathBuf {
+        under(&self.data_dir, path)
+    }
+
+    /// URL prefix blog images are served under. Inside the static directory
+    /// they are reachable through `/static`; anywhere else they get their own
+    /// mount at `/blog/images`.
+    pub fn blog_images_url(&self) -> String {
+        match self.blog_images.strip_prefix(&self.static_dir) {
+            Ok(rel) => {
+                let rel: Vec<_> = rel.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+                format!("/static/{}", rel.join("/"))
+            }
+            Err(_) => "/blog/images".to_string(),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::net::SocketAddr;
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request},
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri, Version},
+    response::{IntoResponse, Response},
+};
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use solarized::{
+    print_fancy,
+    BLUE, RED,
+    BOLD,
+    PrintMode::NewLine,
+};
+
+use crate::ip_filter::ClientIp;
+
+/// Request extension set on everything accepted by the HTTPS listener, so
+/// handlers can tell which scheme the client actually used.
+#[derive(Clone, Copy)]
+pub struct Https;
+
+/// Headers that only describe the client <-> webify connection and must not be
+/// forwarded (RFC 9110 §7.6.1).
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Cookies webify sets for itself. They authenticate against webify, so an
+/// upstream must never see them.
+const OWN_COOKIES: &[&str] = &[
+    crate::auth_guard::GUARD_COOKIE,
+    crate::file_gate::FILE_GATE_COOKIE,
+    crate::forum_sessions::FORUM_SESSION_COOKIE,
+    crate::forum_sessions::LEGACY_USERNAME_COOKIE,
+];
+
+fn log_err(msg: &str) {
+    print_fancy(&[("[proxy] ", BLUE, vec![]), (msg, RED, vec![BOLD])], NewLine);
+}
+
+/// Rewrite `Cookie` without webify's own session cookies.
+fn strip_own_cookies(headers: &mut HeaderMap) {
+    let kept: Vec<String> = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .map(str::trim)
+        .filter(|pair| {
+            let name = pair.split('=').next().unwrap_or("").trim();
+            !pair.is_empty() && !OWN_COOKIES.contains(&name)
+        })
+        .map(str::to_string)
+        .collect();
+    headers.remove(header::COOKIE);
+    if let Ok(value) = HeaderValue::from_str(&kept.join("; "))
+        && !kept.is_empty()
+    {
+        headers.insert(header::COOKIE, value);
+    }
+}
+
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<String> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|t| t.trim().to_ascii_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect();
+    for name in listed {
+        headers.remove(name.as_str());
+    }
+    for name in HOP_BY_HOP {
+        headers.remove(*name);
+    }
+}
+
+fn is_upgrade(headers: &HeaderMap) -> bool {
+    headers.contains_key(header::UPGRADE)
+        && headers
+            .get_all(header::CONNECTION)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+/// A `["http://host:port", "proxy"]` route: forwards everything under `prefix`
+/// to the upstream, streaming bodies both ways and tunnelling upgraded
+/// (WebSocket) connections.
+pub struct Proxy {
+    upstream: String,
+    authority: String,
+    base_path: String,
+    prefix: String,
+    client: Client<HttpConnector, Body>,
+}
+
+impl Proxy {
+    pub fn new(upstream: &str, route: &str) -> Result<Self, String> {
+        let uri: Uri = upstream
+            .parse()
+            .map_err(|e| format!("invalid upstream URL {upstream}: {e}"))?;
+        if uri.scheme_str() != Some("http") {
+            return Err(format!("upstream {upstream} must be an http:// URL"));
+        }
+        let authority = uri
+            .authority()
+            .ok_or_else(|| format!("upstream {upstream} has no host"))?
+            .to_string();
+        Ok(Self {
+            upstream: upstream.to_string(),
+            authority,
+            base_path: uri.path().trim_end_matches('/').to_string(),
+            prefix: route.trim_end_matches('/').to_string(),
+            client: Client::builder(TokioExecutor::new()).build_http(),
+        })
+    }
+
+    /// Map `/prefix/rest?query` onto `http://upstream/base/rest?query`.
+    fn target(&self, uri: &Uri) -> Result<Uri, axum::http::Error> {
+        let rest = uri.path().strip_prefix(self.prefix.as_str()).unwrap_or(uri.path());
+        let mut path_and_query = format!("{}{}", self.base_path, rest);
+        if path_and_query.is_empty() {
+            path_and_query.push('/');
+        }
+        if let Some(query) = uri.query() {
+            path_and_query.push('?');
+            path_and_query.push_str(query);
+        }
+        Uri::builder()
+            .scheme("http")
+            .authority(self.authority.as_str())
+            .path_and_query(path_and_query)
+            .build()
+    }
+
+    pub async fn forward(&self, mut req: Request) -> Response {
+        let upgrade = is_upgrade(req.headers());
+        let upgrade_protocol = req.headers().get(header::UPGRADE).cloned();
+        let client_upgrade = upgrade.then(|| hyper::upgrade::on(&mut req));
+        let client_ip = req
+            .extensions()
+            .get::<ClientIp>()
+            .map(|ClientIp(ip)| *ip)
+            .or_else(|| req.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip()))
+            .map(|ip| ip.to_string());
+        let proto = if req.extensions().get::<Https>().is_some() { "https" } else { "http" };
+        let host = req
+            .headers()
+            .get(header::HOST)
+            .cloned()
+            .or_else(|| req.uri().authority().and_then(|a| HeaderValue::from_str(a.as_str()).ok()));
+        let target = match self.target(req.uri()) {
+            Ok(uri) => uri,
+            Err(e) => {
+                log_err(&format!("could not build upstream URI for {}: {e}", req.uri()));
+                return (StatusCode::BAD_GATEWAY, "Bad Gateway").into_response();
+            }
+        };
+
+        let (mut parts, body) = req.into_parts();
+        parts.uri = target;
+        parts.version = Version::HTTP_11;
+        let headers = &mut parts.headers;
+        strip_hop_by_hop(headers);
+        strip_own_cookies(headers);
+        headers.remove(header::HOST);
+        if let (true, Some(protocol)) = (upgrade, upgrade_protocol) {
+            headers.insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+            headers.insert(header::UPGRADE, protocol);
+        }
+        // Only the address webify resolved, which already accounts for any
+        // trusted proxies in front of it; untrusted hops are not passed on.
+        match client_ip.as_deref().map(HeaderValue::from_str) {
+            Some(Ok(value)) => {
+                headers.insert("x-forwarded-for", value);
+            }
+            _ => {
+                headers.remove("x-forwarded-for");
+            }
+        }
+        headers.insert("x-forwarded-proto", HeaderValue::from_static(proto));
+        match host {
+            Some(host) => {
+                headers.insert("x-forwarded-host", host);
+            }
+            None => {
+                headers.remove("x-forwarded-host");
+            }
+        }
+
+        let mut res = match self.client.request(Request::from_parts(parts, body)).await {
+            Ok(res) => res,
+            Err(e) => {
+                log_err(&format!("{} unreachable: {e}", self.upstream));
+                return (StatusCode::BAD_GATEWAY, "Bad Gateway").into_response();
+            }
+        };
+
+        if res.status() == StatusCode::SWITCHING_PROTOCOLS {
+            if let Some(client_upgrade) = client_upgrade {
+                let upstream_upgrade = hyper::upgrade::on(&mut res);
+                tokio::spawn(async move {
+                    match tokio::try_join!(client_upgrade, upstream_upgrade) {
+                        Ok((client, upstream)) => {
+                            let _ = tokio::io::copy_bidirectional(
+                                &mut TokioIo::new(client),
+                                &mut TokioIo::new(upstream),
+                            )
+                            .await;
+                        }
+                        Err(e) => log_err(&format!("upgrade failed: {e}")),
+                    }
+                });
+                // The 101 keeps its Connection/Upgrade headers so the client completes the handshake.
+                return res.map(|_| Body::empty());
+            }
+        }
+        strip_hop_by_hop(res.headers_mut());
+        res.map(Body::new)
+    }
+}
@@ -0,0 +1,141 @@
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{extract::ConnectInfo, middleware::AddExtension, Extension};
+use axum_server::accept::Accept;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tower::Layer;
+
+use crate::ip_filter::IpNet;
+
+/// First 12 bytes of every PROXY protocol v2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest possible v1 header, CRLF included.
+const V1_MAX_LEN: usize = 107;
+/// How long a client may take to send its header before it is dropped.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("PROXY protocol: {msg}"))
+}
+
+/// Parse a v1 line such as `PROXY TCP4 203.0.113.7 10.0.0.1 51234 443`.
+fn parse_v1(line: &str) -> io::Result<Option<SocketAddr>> {
+    let mut parts = line.split(' ');
+    if parts.next() != Some("PROXY") {
+        return Err(invalid("missing PROXY prefix"));
+    }
+    match parts.next() {
+        Some("TCP4") | Some("TCP6") => {}
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(invalid("unknown v1 protocol")),
+    }
+    let (Some(src), Some(_dst), Some(port), Some(_dport)) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid("truncated v1 header"));
+    };
+    let ip: IpAddr = src.parse().map_err(|_| invalid("bad v1 source address"))?;
+    let port: u16 = port.parse().map_err(|_| invalid("bad v1 source port"))?;
+    Ok(Some(SocketAddr::new(ip, port)))
+}
+
+/// Parse the address block of a v2 PROXY command.
+fn parse_v2(family: u8, block: &[u8]) -> io::Result<Option<SocketAddr>> {
+    match family >> 4 {
+        // AF_INET: src(4) dst(4) sport(2) dport(2)
+        0x1 if block.len() >= 12 => {
+            let ip = Ipv4Addr::new(block[0], block[1], block[2], block[3]);
+            let port = u16::from_be_bytes([block[8], block[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        // AF_INET6: src(16) dst(16) sport(2) dport(2)
+        0x2 if block.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&block[..16]);
+            let port = u16::from_be_bytes([block[32], block[33]]);
+            Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port)))
+        }
+        0x1 | 0x2 => Err(invalid("truncated v2 address block")),
+        // AF_UNSPEC / AF_UNIX: nothing usable, keep the peer address.
+        _ => Ok(None),
+    }
+}
+
+/// Read a v1 or v2 header off the front of the stream, consuming exactly its
+/// bytes so the HTTP or TLS handshake that follows is untouched. `None` means
+/// the balancer sent no client address (v1 UNKNOWN, v2 LOCAL health checks).
+pub async fn read_header(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+    if start == V2_SIGNATURE {
+        let mut fixed = [0u8; 4];
+        stream.read_exact(&mut fixed).await?;
+        let [version_command, family, len_hi, len_lo] = fixed;
+        if version_command >> 4 != 0x2 {
+            return Err(invalid("unsupported v2 version"));
+        }
+        let mut block = vec![0u8; u16::from_be_bytes([len_hi, len_lo]) as usize];
+        stream.read_exact(&mut block).await?;
+        return match version_command & 0x0f {
+            0x0 => Ok(None),
+            0x1 => parse_v2(family, &block),
+            _ => Err(invalid("unknown v2 command")),
+        };
+    }
+    if !start.starts_with(b"PROXY ") {
+        return Err(invalid("connection did not start with a PROXY header"));
+    }
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| invalid("v1 header is not ASCII"))?;
+    parse_v1(line)
+}
+
+/// axum-server acceptor for listeners with `proxy_protocol = true`. It reads
+/// the header before any HTTP or TLS bytes and hands the client address to the
+/// app as `ConnectInfo<SocketAddr>`, where whitelists, rate limits and the
+/// access log pick it up. With `trusted_proxies` set, only those peers may
+/// connect at all.
+#[derive(Clone)]
+pub struct ProxyAcceptor {
+    trusted: Arc<Vec<IpNet>>,
+}
+
+impl ProxyAcceptor {
+    pub fn new(trusted: Vec<IpNet>) -> Self {
+        Self { trusted: Arc::new(trusted) }
+    }
+}
+
+impl<S: Send + 'static> Accept<TcpStream, S> for ProxyAcceptor {
+    type Stream = TcpStream;
+    type Service = AddExtension<S, ConnectInfo<SocketAddr>>;
+    type Future = Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, mut stream: TcpStream, service: S) -> Self::Future {
+        let trusted = self.trusted.clone();
+        Box::pin(async move {
+            let peer = stream.peer_addr()?;
+            if !trusted.is_empty() && !trusted.iter().any(|net| net.contains(peer.ip())) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("PROXY protocol: {peer} is not in trusted_proxies"),
+                ));
+            }
+            let client = tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "PROXY protocol: header timed out"))??;
+            let addr = client.unwrap_or(peer);
+            Ok((stream, Extension(ConnectInfo(addr)).layer(service)))
+        })
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimit;
+use crate::hosts;
+
+const EVICT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Token bucket: holds up to `requests` tokens and refills at
+/// `requests / per_seconds` tokens per second.
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    capacity: f64,
+    rate: f64,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+}
+
+/// In-memory counters for every `[[rate_limit]]` rule, keyed by rule and client
+/// identity. Lives in `AppState` so budgets survive config hot-reloads.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Spend one request from every `(key, rule)` budget, or from none of them:
+    /// a request refused by one budget doesn't use up the others. When any is
+    /// exhausted, returns how long until all of them would allow a request.
+    pub fn check_all(&self, charges: &[(String, &RateLimit)]) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut wait = Duration::ZERO;
+        for (key, rule) in charges {
+            let capacity = rule.requests.max(1) as f64;
+            let rate = capacity / rule.per_seconds.max(1) as f64;
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+                tokens: capacity,
+                last: now,
+                capacity,
+                rate,
+            });
+            // Budgets edited in config.toml take effect on the next request.
+            bucket.capacity = capacity;
+            bucket.rate = rate;
+            bucket.refill(now);
+            if bucket.tokens < 1.0 {
+                wait = wait.max(Duration::from_secs_f64((1.0 - bucket.tokens) / rate));
+            }
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for (key, _) in charges {
+            if let Some(bucket) = buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop buckets that have refilled completely; they are indistinguishable
+    /// from a fresh one.
+    fn evict_idle(&self) {
+        let now = Instant::now();
+        self.buckets.lock().unwrap().retain(|_, bucket| {
+            bucket.refill(now);
+            bucket.tokens < bucket.capacity
+        });
+    }
+}
+
+pub async fn eviction_loop(limiter: Arc<RateLimiter>) {
+    let mut ticker = tokio::time::interval(EVICT_INTERVAL);
+    loop {
+        ticker.tick().await;
+        limiter.evict_idle();
+    }
+}
+
+/// Bucket key for `identity` under `rule`. Built from what the rule covers
+/// rather than its position, so reordering or inserting rules in a hot reload
+/// keeps each budget's counters with the rule they belong to.
+pub fn bucket_key(rule: &RateLimit, identity: &str) -> String {
+    let methods: Vec<String> = rule.methods.iter().map(|m| m.to_ascii_uppercase()).collect();
+    format!(
+        "{}|{}|{}|{}|{identity}",
+        rule.sites.join(","),
+        rule.paths.join(","),
+        methods.join(","),
+        rule.by
+    )
+}
+
+/// Every rule whose sites, paths and methods cover this request. A request
+/// counts against each of their budgets.
+pub fn matching_rules<'a>(
+    rules: &'a [RateLimit],
+    host: &'a str,
+    path: &'a str,
+    method: &'a str,
+) -> impl Iterator<Item = &'a RateLimit> + 'a {
+    rules.iter().filter(move |r| {
+        let site_match = hosts::rank(&r.sites, host).is_some();
+        let path_match = r.paths.iter().any(|p| {
+            let prefix = p.trim_end_matches('/');
+            path == prefix || path.starts_with(&format!("{}/", prefix))
+        });
+        let method_match = r.methods.is_empty()
+            || r.methods.iter().any(|m| m.eq_ignore_ascii_case(method));
+        site_match && path_match && method_match
+    })
+}
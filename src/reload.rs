@@ -0,0 +1,128 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use solarized::{
+    print_fancy,
+    BLUE, CYAN, GREEN, RED,
+    BOLD,
+    PrintMode::NewLine,
+};
+
+use crate::AppState;
+use crate::routes::{build_sites, SharedSites};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+fn log_info(msg: &str) {
+    print_fancy(&[("[reload] ", BLUE, vec![]), (msg, CYAN, vec![])], NewLine);
+}
+
+fn log_ok(msg: &str) {
+    print_fancy(&[("[reload] ", BLUE, vec![]), (msg, GREEN, vec![BOLD])], NewLine);
+}
+
+fn log_err(msg: &str) {
+    print_fancy(&[("[reload] ", BLUE, vec![]), (msg, RED, vec![BOLD])], NewLine);
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Re-parse config.toml and forum.toml and swap freshly built per-host routers
+/// in behind the outer router. Long-lived state (database, interaction rooms,
+/// streams, ACME challenges, metrics) is carried over so nothing live is
+/// dropped. On a parse error, or routes that would not build, the current
+/// routers stay in place.
+pub async fn reload(sites: &SharedSites) -> Result<(), String> {
+    let paths = crate::paths::get();
+    let contents = std::fs::read_to_string(&paths.config)
+        .map_err(|e| format!("could not read {}: {e}", paths.config.display()))?;
+    let config = crate::config::parse_config(&contents)
+        .map_err(|e| format!("{}: {e}", paths.config.display()))?;
+    let conflicts = crate::check::route_conflicts(&config);
+    if !conflicts.is_empty() {
+        let listed: Vec<String> = conflicts.iter().map(|(subject, message)| format!("{subject}: {message}")).collect();
+        return Err(format!("{}: {}", paths.config.display(), listed.join("; ")));
+    }
+    let forum_config = crate::forum::load_forum_config()
+        .map_err(|e| format!("{}: {e}", paths.forum_config.display()))?;
+
+    let old = sites.read().await.state.clone();
+    crate::forum::seed_categories(&old.forum_db, &forum_config).await;
+    if let Some(permanent_rooms) = &config.permanent_rooms {
+        old.interaction.ensure_permanent_rooms(permanent_rooms).await;
+    }
+    let state = Arc::new(AppState {
+        config: Arc::new(config),
+        forum_config: Arc::new(forum_config),
+        forum_db: old.forum_db.clone(),
+        access_rules: old.access_rules.clone(),
+        db_file_guards: old.db_file_guards.clone(),
+        tera: old.tera.clone(),
+        interaction: old.interaction.clone(),
+        stream: old.stream.clone(),
+        acme_challenges: old.acme_challenges.clone(),
+        rate_limiter: old.rate_limiter.clone(),
+        metrics: old.metrics.clone(),
+    });
+    // Built on its own task so a route axum still refuses surfaces as an
+    // error here rather than ending the watcher.
+    let new_sites = tokio::spawn(build_sites(state))
+        .await
+        .map_err(|_| "building the site routers panicked; see the message above".to_string())?;
+    *sites.write().await = Arc::new(new_sites);
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn hangup(signal: &mut Option<tokio::signal::unix::Signal>) {
+    match signal {
+        Some(s) => {
+            s.recv().await;
+        }
+        None => std::future::pending::<()>().await,
+    }
+}
+
+/// Watch config.toml / forum.toml for changes (by polling their mtimes) and
+/// listen for SIGHUP, reloading the site routers whenever either fires.
+/// Listener settings (scope, ports, SSL) are only read at startup.
+pub async fn watch(sites: SharedSites) {
+    #[cfg(unix)]
+    let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .map_err(|e| log_err(&format!("could not install SIGHUP handler: {e}")))
+        .ok();
+    let paths = crate::paths::get();
+    let mut last_seen = (modified(&paths.config), modified(&paths.forum_config));
+    let mut ticker = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        #[cfg(unix)]
+        let trigger = tokio::select! {
+            _ = ticker.tick() => None,
+            _ = hangup(&mut sighup) => Some("SIGHUP received"),
+        };
+        #[cfg(not(unix))]
+        let trigger: Option<&str> = {
+            ticker.tick().await;
+            None
+        };
+        let seen = (modified(&paths.config), modified(&paths.forum_config));
+        let trigger = match trigger {
+            Some(t) => t,
+            None if seen != last_seen => "config change detected",
+            None => continue,
+        };
+        last_seen = seen;
+        log_info(&format!(
+            "{trigger}; reloading {} and {}",
+            paths.config.display(),
+            paths.forum_config.display()
+        ));
+        match reload(&sites).await {
+            Ok(()) => log_ok("routes, whitelists and guards reloaded"),
+            Err(e) => log_err(&format!("reload failed, keeping previous config: {e}")),
+        }
+    }
+}
@@ -5,7 +5,7 @@ use axum::{
     extract::{Path, DefaultBodyLimit, Query, Request, ConnectInfo, Form},
     response::{Html, IntoResponse, Redirect},
     routing::{
-        get, post, get_service
+        any, get, post, get_service
     },
     http::{
         header, HeaderMap, StatusCode
@@ -14,6 +14,10 @@ use axum::{
 };
 use axum::extract::State;
 use tower_http::services::{ServeDir, ServeFile};
+use tower_http::compression::{
+    predicate::{NotForContentType, Predicate, SizeAbove},
+    CompressionLayer,
+};
 use pulldown_cmark::{Parser, Options, html};
 use crate::media::render_html_with_media;
 use crate::upload::upload;
@@ -28,8 +32,15 @@ use sqlx;
 use crate::AppState;
 use crate::forum::*;
 use crate::auth_guard;
+use crate::forum_sessions;
+use crate::client_cert;
 use crate::admin;
 use crate::file_gate;
+use crate::ip_filter::{self, IpList, IpNet};
+use crate::rate_limit;
+use crate::access_log::AccessLog;
+use crate::site_headers::HeaderPolicy;
+use crate::hosts;
 use solarized::{
     print_fancy,
     VIOLET, CYAN, RED, ORANGE,
@@ -39,29 +50,15 @@ use solarized::{
 use std::collections::HashMap;
 use axum_extra::extract::CookieJar;
 use std::sync::Arc;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use tower::Service;
 
 async fn not_found() -> impl IntoResponse {
-    let file_path = "static/error.html";
-    let custom_404_html = fs::read_to_string(file_path).await.unwrap_or_else(|_| {
-    String::from(r#"
-<!doctype html>
-<html lang="en-US">
-<head>
-    <meta charset="utf-8" />
-    <meta name="viewport" content="width=device-width, initial-scale=1.0, user-scalable=yes" />
-    <title>guacamole</title>
-    <link rel="stylesheet" type="text/css" href="https://thomasf.github.io/solarized-css/solarized-dark.min.css"></link>
-    <meta name="viewport" content="width=device-width, initial-scale=1">
-</head>
-<body>
-    <h1>ERROR</h1>
-    <p>You shouldn't be here. Please go away.</p>
-</body>
-</html>
-"#)
-    });
+    let file_path = crate::paths::get().static_dir.join("error.html");
+    // Served as-is, not through Tera; the built-in page covers a missing file.
+    let custom_404_html = fs::read_to_string(file_path)
+        .await
+        .unwrap_or_else(|_| crate::constants::EXAMPLE_ERROR.to_string());
     (StatusCode::NOT_FOUND, Html(custom_404_html))
 }
 
@@ -70,9 +67,66 @@ pub struct LiveQuery {
     pub offset: Option<u64>,
 }
 
-pub async fn app(state: Arc<AppState>) -> Router {
+/// The per-host routers built from one config snapshot, together with the state
+/// they were built against. Replaced as a whole when the config is hot-reloaded.
+pub struct Sites {
+    pub state: Arc<AppState>,
+    routers: HashMap<String, Router>,
+    header_policies: HashMap<String, Arc<HeaderPolicy>>,
+    whitelists: HashMap<String, IpList>,
+    blacklists: HashMap<String, IpList>,
+    trusted_proxies: Vec<IpNet>,
+}
+
+impl Sites {
+    /// Real client address for a connection from `peer`, honouring
+    /// X-Forwarded-For only when `peer` is one of the `trusted_proxies`.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        ip_filter::client_ip(peer, headers, &self.trusted_proxies)
+    }
+
+    /// The `[headers]` policy for `host`: exact host, then wildcard, then "default".
+    pub fn header_policy(&self, host: &str) -> Option<Arc<HeaderPolicy>> {
+        hosts::lookup(&self.header_policies, host).cloned()
+    }
+}
+
+/// `ServeDir` that answers with a `.br`, `.zst` or `.gz` sibling of the requested
+/// file when one exists and the client accepts that encoding.
+fn serve_dir(path: impl AsRef<std::path::Path>) -> ServeDir {
+    ServeDir::new(path)
+        .precompressed_br()
+        .precompressed_zstd()
+        .precompressed_gzip()
+}
+
+/// Negotiated br/zstd/gzip for a site's responses. Images, audio, video,
+/// archives and fonts are already compressed; ranges and upgrades are left alone.
+fn compression_layer(min_size: u16) -> CompressionLayer<impl Predicate> {
+    let predicate = SizeAbove::new(min_size)
+        .and(NotForContentType::GRPC)
+        .and(NotForContentType::IMAGES)
+        .and(NotForContentType::SSE)
+        .and(NotForContentType::const_new("video/"))
+        .and(NotForContentType::const_new("audio/"))
+        .and(NotForContentType::const_new("font/"))
+        .and(NotForContentType::const_new("application/zip"))
+        .and(NotForContentType::const_new("application/gzip"))
+        .and(NotForContentType::const_new("application/zstd"))
+        .and(NotForContentType::const_new("application/x-7z-compressed"))
+        .and(|status: StatusCode, _: axum::http::Version, headers: &HeaderMap, _: &axum::http::Extensions| {
+            status != StatusCode::SWITCHING_PROTOCOLS && !headers.contains_key(header::CONTENT_RANGE)
+        });
+    CompressionLayer::new().compress_when(predicate)
+}
+
+/// Handle the outer router reads on every request; the reload task swaps the
+/// inner `Arc<Sites>` so in-flight requests finish against the old snapshot.
+pub type SharedSites = Arc<tokio::sync::RwLock<Arc<Sites>>>;
+
+pub async fn build_sites(state: Arc<AppState>) -> Sites {
+    let paths = crate::paths::get();
     let mut site_routers = HashMap::new();
-    let whitelists = Arc::new(state.config.whitelists.clone());
     for (domain, routes) in &state.config.sites {
         let mut router = Router::new()
             .route("/thumbnail/{*path}", get(generate_thumbnail))
@@ -86,15 +140,15 @@ pub async fn app(state: Arc<AppState>) -> Router {
             .route("/blog/upload-image", post(crate::blog::upload_image)
                 .layer(DefaultBodyLimit::max(64 * 1024 * 1024)))
             .route("/blog/{post_name}", get(render_post))
-            .nest_service("/static", ServeDir::new("static"))
-            .nest_service("/templates", ServeDir::new("templates"))
-            .nest_service("/uploads", ServeDir::new("uploads"))
-            .route("/favicon.ico", get_service(ServeFile::new("static/favicon.ico")))
-            .nest_service("/css", ServeDir::new("css"))
-            .nest_service("/styles", ServeDir::new("styles"))
-            .nest_service("/js", ServeDir::new("js"))
-            .nest_service("/scripts", ServeDir::new("scripts"))
-            .nest_service("/images", ServeDir::new("images"))
+            .nest_service("/static", serve_dir(&paths.static_dir))
+            .nest_service("/templates", serve_dir(paths.data_dir.join("templates")))
+            .nest_service("/uploads", serve_dir(&paths.uploads))
+            .route("/favicon.ico", get_service(ServeFile::new(paths.static_dir.join("favicon.ico"))))
+            .nest_service("/css", serve_dir(paths.data_dir.join("css")))
+            .nest_service("/styles", serve_dir(paths.data_dir.join("styles")))
+            .nest_service("/js", serve_dir(paths.data_dir.join("js")))
+            .nest_service("/scripts", serve_dir(paths.data_dir.join("scripts")))
+            .nest_service("/images", serve_dir(paths.data_dir.join("images")))
             .route("/auth/login", get(auth_guard::guard_login))
             .route("/auth/google", get(auth_guard::guard_google))
             .route("/auth/callback", get(auth_guard::guard_callback))
@@ -114,6 +168,10 @@ pub async fn app(state: Arc<AppState>) -> Router {
             )
             .route("/ws/interaction/{room_id}", get(interaction::ws_handler))
             .fallback(get(not_found));
+        // Blog images kept outside the static directory need their own mount.
+        if !paths.blog_images.starts_with(&paths.static_dir) {
+            router = router.nest_service("/blog/images", serve_dir(&paths.blog_images));
+        }
         let mut config_paths: std::collections::HashSet<String> = std::collections::HashSet::new();
         for (path, settings) in routes {
             config_paths.insert(path.clone());
@@ -131,6 +189,9 @@ pub async fn app(state: Arc<AppState>) -> Router {
                         .route("/register", get(register_form).post(register))
                         .route("/login", get(login_form).post(login))
                         .route("/logout", get(logout))
+                        .route("/sessions", get(forum_sessions::list_sessions))
+                        .route("/sessions/{id}/revoke", post(forum_sessions::revoke_session))
+                        .route("/sessions/revoke-others", post(forum_sessions::revoke_other_sessions))
                         .route("/verify", get(verify_email))
                         .route("/auth/google", get(login_google))
                         .route("/auth/google/callback", get(callback_google))
@@ -146,7 +207,7 @@ pub async fn app(state: Arc<AppState>) -> Router {
                     router = router.nest(path, forum_routes);
                 }
                 [settings_type, slides_dir] if settings_type == "slideshow" => {
-                    let slides_dir_clone = slides_dir.clone();
+                    let slides_dir_clone = crate::paths::config_path(slides_dir);
                     let autoplay = state.config.slideshow_autoplay;
                     let timer = state.config.slideshow_timer;
                     router = router.route(
@@ -161,7 +222,7 @@ pub async fn app(state: Arc<AppState>) -> Router {
                 }
                 [dir_path, mode] if mode == "static" => {
                     println!("The correct mode is selected for wiki");
-                    let serve_dir = ServeDir::new(dir_path);
+                    let serve_dir = serve_dir(paths.in_data_dir(dir_path));
                     let path_no_slash = path.trim_end_matches('/').to_string();
                     let path_slash = format!("{}/", path_no_slash);
                     let redirect_target = path_slash.clone();
@@ -172,7 +233,7 @@ pub async fn app(state: Arc<AppState>) -> Router {
                 }
                 [file_path, watch_file, mode] if mode == "live" => {
                     let file_clone = file_path.clone();
-                    let watch_clone = watch_file.clone();
+                    let watch_clone = crate::paths::config_path(watch_file);
                     let path_clone = path.clone();
                     router = router.route(&path_clone, get(move |s: State<Arc<AppState>>| {
                         let f = file_clone.clone();
@@ -209,8 +270,33 @@ pub async fn app(state: Arc<AppState>) -> Router {
                         get(handle_php).post(handle_php)
                     );
                 }
+                [upstream, mode] if mode == "proxy" => {
+                    match crate::proxy::Proxy::new(upstream, path) {
+                        Ok(proxy) => {
+                            let proxy = Arc::new(proxy);
+                            let base = path.trim_end_matches('/');
+                            let handler = any(move |req: Request| {
+                                let p = proxy.clone();
+                                async move { p.forward(req).await }
+                            });
+                            router = router
+                                .route(if base.is_empty() { "/" } else { base }, handler.clone())
+                                .route(&format!("{}/{{*rest}}", base), handler);
+                        }
+                        Err(e) => {
+                            print_fancy(&[
+                                ("Error", RED, vec![BOLD]),
+                                (": ", CYAN, vec![]),
+                                (path, VIOLET, vec![]),
+                                (": ", CYAN, vec![]),
+                                (&e, ORANGE, vec![]),
+                            ], NewLine);
+                        }
+                    }
+                }
                 [file_path, media_dir, ..] => {
                     let file_clone = file_path.clone();
+                    let media_dir = crate::paths::config_path(media_dir);
                     let media_dir_clone = media_dir.clone();
                     let media_route = path.trim_start_matches('/').to_string();
                     let sort_method = settings.get(2).map(|s| s.to_string());
@@ -223,7 +309,7 @@ pub async fn app(state: Arc<AppState>) -> Router {
                             render_html_with_media(&s.tera, &f, &m, &r, sort.as_deref()).await
                         }
                     }));
-                    let serve_dir = ServeDir::new(media_dir);
+                    let serve_dir = ServeDir::new(&media_dir);
                     let static_route = path.trim_start_matches('/');
                     router = router
                         .nest_service(&format!("/static/{static_route}"), serve_dir);
@@ -240,8 +326,10 @@ pub async fn app(state: Arc<AppState>) -> Router {
         }
         // Add admin dashboard routes for any configured dashboard whose domain matches this site
         for dashboard in &state.config.admin_dashboards {
-            let site_match = dashboard.domain.is_empty()
-                || domain.eq_ignore_ascii_case(&dashboard.domain);
+            // A wildcard site router serves every subdomain, so mount dashboards
+            // for any of them; the fallback picks the right one per request.
+            let site_match = hosts::rank_domain(&dashboard.domain, domain).is_some()
+                || hosts::matches(domain, &dashboard.domain);
             if !site_match {
                 continue;
             }
@@ -355,19 +443,23 @@ pub async fn app(state: Arc<AppState>) -> Router {
         if !config_paths.contains("/watch/whep/{session_id}") {
             router = router.route("/watch/whep/{session_id}", axum::routing::patch(stream::whep_patch));
         }
+        if state.config.metrics && !config_paths.contains("/metrics") {
+            router = router.route("/metrics", get(crate::metrics::metrics_handler));
+        }
         let storage_limit = state.config.upload_storage_limit;
+        let upload_metrics = state.metrics.clone();
         match parse_upload_limit(&state.config.upload_size_limit).await {
             Ok(num) => {
                 router = router.route(
                     "/upload",
-                    post(move |headers, multipart| upload(headers, multipart, storage_limit))
+                    post(move |headers, multipart| upload(headers, multipart, storage_limit, upload_metrics.clone()))
                     .layer(DefaultBodyLimit::max(num))
                 );
             },
             Err("disabled") => {
                 router = router.route(
                     "/upload",
-                    post(move |headers, multipart| upload(headers, multipart, storage_limit))
+                    post(move |headers, multipart| upload(headers, multipart, storage_limit, upload_metrics.clone()))
                     .layer(DefaultBodyLimit::disable())
                 );
             },
@@ -385,21 +477,59 @@ pub async fn app(state: Arc<AppState>) -> Router {
                 let default_limit = 2 * 1000 * 1000 * 1000;
                 router = router.route(
                     "/upload",
-                    post(move |headers, multipart| upload(headers, multipart, storage_limit))
+                    post(move |headers, multipart| upload(headers, multipart, storage_limit, upload_metrics.clone()))
                     .layer(DefaultBodyLimit::max(default_limit))
                 );
             }
         }
-        let final_site_router = router.with_state(state.clone());
+        let compress = hosts::lookup(&state.config.compression, domain)
+            .copied()
+            .unwrap_or(false);
+        let mut final_site_router = router.with_state(state.clone());
+        if compress {
+            final_site_router = final_site_router.layer(compression_layer(state.config.compression_min_size));
+        }
         site_routers.insert(domain.clone(), final_site_router);
     }
-    let site_routers_arc = Arc::new(site_routers);
-    let guard_state = state.clone();
+    let mut header_policies = HashMap::new();
+    for (host, site_headers) in &state.config.headers {
+        let (policy, errors) = HeaderPolicy::build(site_headers);
+        for e in &errors {
+            print_fancy(&[
+                ("Error", RED, vec![BOLD]),
+                (": ", CYAN, vec![]),
+                (&format!("headers.\"{host}\""), VIOLET, vec![]),
+                (": ", CYAN, vec![]),
+                (e, ORANGE, vec![]),
+            ], NewLine);
+        }
+        if !policy.is_empty() {
+            header_policies.insert(host.clone(), Arc::new(policy));
+        }
+    }
+    Sites {
+        whitelists: ip_filter::parse_lists(&state.config.whitelists, "whitelist"),
+        blacklists: ip_filter::parse_lists(&state.config.blacklists, "blacklist"),
+        trusted_proxies: IpList::parse(&state.config.trusted_proxies, "trusted_proxies").nets().to_vec(),
+        routers: site_routers,
+        header_policies,
+        state,
+    }
+}
+
+pub async fn app(sites: SharedSites) -> Router {
+    let metrics_sites = sites.clone();
+    let headers_sites = sites.clone();
+    let scope_sites = sites.clone();
+    let access_log = {
+        let current = sites.read().await;
+        AccessLog::start(&current.state.config, sites.clone())
+    };
     // ACME HTTP-01 challenge responder. Registered on the outermost router so it
     // takes precedence over the fallback and bypasses the whitelist / auth-guard
     // checks below — it must be reachable over plain HTTP for cert issuance/renewal.
-    let challenge_store = state.acme_challenges.clone();
-Router::new()
+    let challenge_store = sites.read().await.state.acme_challenges.clone();
+let router = Router::new()
     .route("/.well-known/acme-challenge/{token}", get(move |Path(token): Path<String>| {
         let store = challenge_store.clone();
         async move {
@@ -409,25 +539,79 @@ Router::new()
             }
         }
     }))
-    .fallback(move |headers: HeaderMap, ConnectInfo(addr): ConnectInfo<SocketAddr>, req: Request| {
-        let routers = Arc::clone(&site_routers_arc);
-        let whitelist_map = Arc::clone(&whitelists);
-        let gs = guard_state.clone();
+    .fallback(move |headers: HeaderMap, ConnectInfo(addr): ConnectInfo<SocketAddr>, mut req: Request| {
+        let sites = Arc::clone(&sites);
         async move {
+            let current = sites.read().await.clone();
+            let routers = &current.routers;
+            let whitelist_map = &current.whitelists;
+            let blacklist_map = &current.blacklists;
+            let gs = current.state.clone();
             let hostname = headers
                 .get(header::HOST)
                 .and_then(|h| h.to_str().ok())
                 .and_then(|h| h.split(':').next())
                 .unwrap_or("")
                 .to_string();
-            let client_ip = addr.ip().to_string();
-            if let Some(ips) = whitelist_map.get(&hostname).or_else(|| whitelist_map.get("default")) {
-                if !ips.is_empty() && !ips.contains(&client_ip) {
+            let client_ip = current.client_ip(addr.ip(), &headers);
+            req.extensions_mut().insert(ip_filter::ClientIp(client_ip));
+            let blacklisted = [hosts::find(blacklist_map, &hostname).map(|(_, list)| list), blacklist_map.get("default")]
+                .into_iter()
+                .flatten()
+                .any(|list| list.matches(client_ip));
+            if blacklisted {
+                return (StatusCode::FORBIDDEN, "Access Denied").into_response();
+            }
+            if let Some(ips) = hosts::lookup(whitelist_map, &hostname) {
+                if !ips.is_empty() && !ips.matches(client_ip) {
                     return (StatusCode::FORBIDDEN, "Access Denied").into_response();
                 }
             }
-            // Auth guard: check email-based access control before routing
             let path = req.uri().path().to_string();
+            let method = req.method().as_str().to_string();
+            let mut charges = Vec::new();
+            for rule in rate_limit::matching_rules(&gs.config.rate_limits, &hostname, &path, &method) {
+                let user = if rule.by == "user" {
+                    let cookie_header = req.headers()
+                        .get(header::COOKIE)
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or("");
+                    match auth_guard::extract_cookie_value(cookie_header, auth_guard::GUARD_COOKIE) {
+                        Some(t) => auth_guard::validate_session(&gs.forum_db, &t).await,
+                        None => forum_sessions::username_from_header(&gs.forum_db, cookie_header).await,
+                    }
+                } else {
+                    None
+                };
+                let identity = match user {
+                    Some(email) => format!("user:{email}"),
+                    None => format!("ip:{client_ip}"),
+                };
+                let key = rate_limit::bucket_key(rule, &identity);
+                // Identical rules share one bucket; charge it once.
+                if !charges.iter().any(|(k, _)| *k == key) {
+                    charges.push((key, rule));
+                }
+            }
+            if let Err(wait) = gs.rate_limiter.check_all(&charges) {
+                let retry_after = (wait.as_secs_f64().ceil() as u64).max(1).to_string();
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after)],
+                    "Too Many Requests",
+                ).into_response();
+            }
+            // Client certificate guard: only the HTTPS listener sets PeerCert.
+            if let Some(guard) = client_cert::find_guard(&gs.config.client_cert_guards, &hostname, &path) {
+                let allowed = req.extensions()
+                    .get::<client_cert::PeerCert>()
+                    .and_then(|cert| cert.0.as_deref())
+                    .is_some_and(|id| client_cert::identity_allowed(guard, id));
+                if !allowed {
+                    return (StatusCode::FORBIDDEN, "Client Certificate Required").into_response();
+                }
+            }
+            // Auth guard: check email-based access control before routing
             if !path.starts_with("/auth/") {
                 let cookie_header = req.headers()
                     .get(header::COOKIE)
@@ -436,11 +620,10 @@ Router::new()
                     .to_string();
 
                 // Check if this path belongs to a configured admin dashboard
-                let matching_dashboard = gs.config.admin_dashboards.iter().find(|d| {
-                    let site_match = d.domain.is_empty() || d.domain.eq_ignore_ascii_case(&hostname);
+                let matching_dashboard = hosts::best(gs.config.admin_dashboards.iter(), |d| {
                     let prefix = d.path.trim_end_matches('/');
                     let pm = path == prefix || path.starts_with(&format!("{}/", prefix));
-                    site_match && pm
+                    if pm { hosts::rank_domain(&d.domain, &hostname) } else { None }
                 });
 
                 if let Some(dashboard) = matching_dashboard {
@@ -526,15 +709,24 @@ Router::new()
                     }
                 }
             }
-            if let Some(router) = routers.get(&hostname) {
+            // Exact host, then a `*.` wildcard, then "default" (see `hosts`).
+            if let Some(router) = hosts::lookup(routers, &hostname) {
                 router.clone().call(req).await.unwrap().into_response()
-            } else if let Some(default) = routers.get("default") {
-                default.clone().call(req).await.unwrap().into_response()
             } else {
                 not_found().await.into_response()
             }
         }
-    })
+    });
+    // Outside the fallback so IP filter 403s, rate limit 429s and auth guard
+    // redirects carry the site's headers too.
+    let router = router
+        .layer(axum::middleware::from_fn_with_state(headers_sites, crate::site_headers::apply))
+        .layer(axum::middleware::from_fn_with_state(scope_sites, crate::template_functions::request_scope))
+        .layer(axum::middleware::from_fn_with_state(metrics_sites, crate::metrics::track));
+    match access_log {
+        Some(log) => router.layer(axum::middleware::from_fn_with_state(log, crate::access_log::record)),
+        None => router,
+    }
 }
 
 async fn render_post(
@@ -542,7 +734,7 @@ async fn render_post(
     headers: HeaderMap,
     Path(post_name): Path<String>,
 ) -> impl IntoResponse {
-    let file_path = format!("static/posts/{}.md", post_name);
+    let file_path = crate::paths::get().posts.join(format!("{}.md", post_name));
     let markdown_content = match fs::read_to_string(&file_path).await {
         Ok(content) => content,
         Err(_) => return Html("Post not found".to_string()).into_response(),
@@ -615,9 +807,6 @@ async fn render_tera_template(
     let template_name = template_path.trim_start_matches("static/");
     match state.tera.render(template_name, &context) {
         Ok(rendered) => Html(rendered).into_response(),
-        Err(e) => {
-            eprintln!("Tera error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
-        }
+        Err(e) => state.tera.error_response(template_name, &e),
     }
 }
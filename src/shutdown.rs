@@ -0,0 +1,88 @@
+use std::future::IntoFuture;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use solarized::{
+    print_fancy,
+    BLUE, CYAN, ORANGE,
+    PrintMode::NewLine,
+};
+
+use crate::AppState;
+
+fn log_info(msg: &str) {
+    print_fancy(&[("[shutdown] ", BLUE, vec![]), (msg, CYAN, vec![])], NewLine);
+}
+
+fn log_warn(msg: &str) {
+    print_fancy(&[("[shutdown] ", BLUE, vec![]), (msg, ORANGE, vec![])], NewLine);
+}
+
+/// Flips to `true` once shutdown has begun; every listener holds a receiver.
+pub type Stop = watch::Receiver<bool>;
+
+async fn terminate() {
+    #[cfg(unix)]
+    {
+        let sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate());
+        match sigterm {
+            Ok(mut s) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = s.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Wait for Ctrl-C or SIGTERM, then tell interaction rooms the server is going
+/// away, close WHIP/WHEP peer connections and signal the listeners to stop.
+pub fn listen(state: Arc<AppState>) -> Stop {
+    let (tx, rx) = watch::channel(false);
+    tokio::spawn(async move {
+        terminate().await;
+        log_info(&format!(
+            "stopping; draining connections for up to {}s",
+            state.config.shutdown_timeout
+        ));
+        state.interaction.broadcast_system("Server is shutting down.").await;
+        state.stream.close_all().await;
+        let _ = tx.send(true);
+    });
+    rx
+}
+
+/// Resolves once shutdown has begun; for `with_graceful_shutdown`.
+pub async fn stopped(mut stop: Stop) {
+    let _ = stop.wait_for(|s| *s).await;
+}
+
+/// Run a listener until it exits. After shutdown begins it gets `timeout` to
+/// finish in-flight requests before it is dropped along with its connections.
+pub async fn drain<S>(server: S, stop: Stop, timeout: Duration) -> std::io::Result<()>
+where
+    S: IntoFuture<Output = std::io::Result<()>>,
+{
+    let server = server.into_future();
+    tokio::pin!(server);
+    tokio::select! {
+        result = &mut server => return result,
+        _ = stopped(stop) => {}
+    }
+    match tokio::time::timeout(timeout, server).await {
+        Ok(result) => result,
+        Err(_) => {
+            log_warn("drain timeout reached; closing remaining connections");
+            Ok(())
+        }
+    }
+}
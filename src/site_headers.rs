@@ -0,0 +1,90 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::config::SiteHeaders;
+use crate::proxy::Https;
+use crate::routes::SharedSites;
+
+/// A site's `[headers."host"]` section with names and values validated.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderPolicy {
+    hsts: Option<HeaderValue>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+fn parse_value(name: &str, value: &str, errors: &mut Vec<String>) -> Option<HeaderValue> {
+    match HeaderValue::from_str(value) {
+        Ok(v) => Some(v),
+        Err(_) => {
+            errors.push(format!("{name}: {value:?} is not a valid header value"));
+            None
+        }
+    }
+}
+
+impl HeaderPolicy {
+    /// Build the policy, skipping entries that aren't valid header names or
+    /// values. The returned messages describe each skipped entry.
+    pub fn build(site: &SiteHeaders) -> (Self, Vec<String>) {
+        let mut errors = Vec::new();
+        let hsts = site.hsts.as_deref().and_then(|v| parse_value("hsts", v, &mut errors));
+        let mut headers = Vec::new();
+        for (name, configured) in [
+            (header::CONTENT_SECURITY_POLICY, &site.content_security_policy),
+            (header::X_FRAME_OPTIONS, &site.x_frame_options),
+            (header::REFERRER_POLICY, &site.referrer_policy),
+            (HeaderName::from_static("permissions-policy"), &site.permissions_policy),
+        ] {
+            if let Some(v) = configured.as_deref().and_then(|v| parse_value(name.as_str(), v, &mut errors)) {
+                headers.push((name, v));
+            }
+        }
+        let mut custom: Vec<_> = site.custom.iter().collect();
+        custom.sort();
+        for (name, configured) in custom {
+            match HeaderName::from_bytes(name.as_bytes()) {
+                Ok(header_name) => {
+                    if let Some(v) = parse_value(name, configured, &mut errors) {
+                        headers.push((header_name, v));
+                    }
+                }
+                Err(_) => errors.push(format!("custom: {name:?} is not a valid header name")),
+            }
+        }
+        (Self { hsts, headers }, errors)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hsts.is_none() && self.headers.is_empty()
+    }
+}
+
+/// Outer-router middleware adding the request host's policy to every response,
+/// including the ones refused before a site router runs. Headers a handler or
+/// proxied upstream already set are left as they are, and HSTS is only sent on
+/// the HTTPS listener.
+pub async fn apply(State(sites): State<SharedSites>, req: Request, next: Next) -> Response {
+    let https = req.extensions().get::<Https>().is_some();
+    let hostname = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split(':').next())
+        .unwrap_or("")
+        .to_string();
+    let policy = sites.read().await.header_policy(&hostname);
+    let mut response = next.run(req).await;
+    let Some(policy) = policy else { return response };
+    let headers = response.headers_mut();
+    if https && let Some(hsts) = &policy.hsts {
+        headers.entry(header::STRICT_TRANSPORT_SECURITY).or_insert_with(|| hsts.clone());
+    }
+    for (name, value) in &policy.headers {
+        headers.entry(name.clone()).or_insert_with(|| value.clone());
+    }
+    response
+}
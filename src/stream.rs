@@ -97,6 +97,28 @@ impl StreamState {
         };
         Ok(Arc::new(self.api.new_peer_connection(config).await?))
     }
+
+    /// Number of WHEP viewers with an open peer connection.
+    pub async fn viewer_count(&self) -> usize {
+        self.viewer_pcs.read().await.len()
+    }
+
+    /// Close the WHIP broadcast and every WHEP viewer connection and mark the
+    /// stream offline. Used on shutdown so peers see a clean close.
+    pub async fn close_all(&self) {
+        let viewers: Vec<_> = self.viewer_pcs.write().await.drain().map(|(_, pc)| pc).collect();
+        for pc in viewers {
+            let _ = pc.close().await;
+        }
+        if let Some(pc) = self.broadcaster_pc.write().await.take() {
+            let _ = pc.close().await;
+        }
+        self.is_live.store(false, Ordering::Relaxed);
+        *self.video_track.write().await = None;
+        *self.audio_track.write().await = None;
+        *self.broadcaster_username.write().await = None;
+        *self.whip_session.write().await = None;
+    }
 }
 
 // ── DB helpers ────────────────────────────────────────────────────────────────
@@ -212,10 +234,7 @@ pub async fn live_handler(
 
     match state.tera.render("stream.html", &ctx) {
         Ok(html) => Html(html).into_response(),
-        Err(e) => {
-            eprintln!("Tera error: {e}");
-            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
-        }
+        Err(e) => state.tera.error_response("stream.html", &e),
     }
 }
 
@@ -238,10 +257,7 @@ pub async fn watch_handler(State(state): State<Arc<AppState>>) -> impl IntoRespo
 
     match state.tera.render("view.html", &ctx) {
         Ok(html) => Html(html).into_response(),
-        Err(e) => {
-            eprintln!("Tera error: {e}");
-            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
-        }
+        Err(e) => state.tera.error_response("view.html", &e),
     }
 }
 
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use pulldown_cmark::{html, Options, Parser};
+use rand::{seq::SliceRandom, rng};
+use serde_json::json;
+use tera::{Tera, Value};
+
+use crate::auth_guard;
+use crate::forum::ForumDb;
+use crate::forum_sessions;
+use crate::hosts;
+use crate::routes::SharedSites;
+use crate::stream::StreamState;
+
+/// Forum prefix used when the request's site has no forum route.
+const DEFAULT_FORUM_PREFIX: &str = "/forum";
+
+/// What template functions need to know about the request being handled.
+#[derive(Clone)]
+struct RequestInfo {
+    /// Cookie header, for `current_user()`.
+    cookies: String,
+    /// Where this site mounts the forum, for `forum_latest()` links.
+    forum_prefix: String,
+}
+
+tokio::task_local! {
+    static REQUEST: RequestInfo;
+}
+
+/// Outer-router middleware making the request's cookies and its site's forum
+/// route visible to template functions rendered while handling it.
+pub async fn request_scope(State(sites): State<SharedSites>, req: Request, next: Next) -> Response {
+    let header_str = |name: header::HeaderName| {
+        req.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or("").to_string()
+    };
+    let cookies = header_str(header::COOKIE);
+    let host = header_str(header::HOST);
+    let hostname = host.split(':').next().unwrap_or("");
+    let forum_prefix = {
+        let current = sites.read().await;
+        hosts::lookup(&current.state.config.sites, hostname)
+            .and_then(|routes| {
+                routes
+                    .iter()
+                    .find(|(_, settings)| matches!(settings.as_slice(), [_, mode] if mode == "forum"))
+            })
+            .map(|(path, _)| path.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| DEFAULT_FORUM_PREFIX.to_string())
+    };
+    REQUEST.scope(RequestInfo { cookies, forum_prefix }, next.run(req)).await
+}
+
+/// Tera functions are synchronous; the database and stream state are not.
+/// Rendering happens inside a handler on the multi-threaded runtime, so the
+/// worker can block on the lookup without stalling other tasks.
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(future))
+}
+
+fn arg_str<'a>(args: &'a HashMap<String, Value>, name: &str) -> Option<&'a str> {
+    args.get(name).and_then(Value::as_str)
+}
+
+fn arg_limit(args: &HashMap<String, Value>, default: usize) -> usize {
+    args.get("limit").and_then(Value::as_u64).map(|n| n as usize).unwrap_or(default)
+}
+
+/// `markdown(path="static/documents/example.md")`: the file rendered to HTML,
+/// without its front matter.
+fn markdown(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let path = arg_str(args, "path").ok_or_else(|| tera::Error::msg("markdown: missing `path`"))?;
+    let content = std::fs::read_to_string(crate::paths::config_path(path))
+        .map_err(|e| tera::Error::msg(format!("markdown: could not read {path}: {e}")))?;
+    let (_, body) = crate::blog::parse_front_matter(&content);
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TABLES);
+    let mut html_output = String::new();
+    html::push_html(&mut html_output, Parser::new_ext(&body, options));
+    Ok(Value::String(html_output))
+}
+
+/// `list_files(dir="static/media", sort="alphanumeric")`: file names in `dir`,
+/// sorted like media routes ("random" shuffles).
+fn list_files(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let dir = arg_str(args, "dir").ok_or_else(|| tera::Error::msg("list_files: missing `dir`"))?;
+    let mut files = block_on(crate::utils::read_media_files(&crate::paths::config_path(dir)))
+        .map_err(|e| tera::Error::msg(format!("list_files: could not read {dir}: {e}")))?;
+    match arg_str(args, "sort") {
+        Some("random") => files.shuffle(&mut rng()),
+        _ => files.sort(),
+    }
+    Ok(json!(files))
+}
+
+/// `blog_posts(limit=5)`: newest posts with slug, title, image and date.
+fn blog_posts(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let mut posts = block_on(crate::blog::list_posts());
+    posts.truncate(arg_limit(args, 10));
+    Ok(json!(posts))
+}
+
+/// Registers the site-author functions on a template set. The database and
+/// stream handles are kept here because templates are built before, and
+/// rebuilt independently of, `AppState`.
+#[derive(Clone)]
+pub struct TemplateFunctions {
+    forum_db: ForumDb,
+    stream: Arc<StreamState>,
+}
+
+impl TemplateFunctions {
+    pub fn new(forum_db: ForumDb, stream: Arc<StreamState>) -> Self {
+        Self { forum_db, stream }
+    }
+
+    pub fn register(&self, tera: &mut Tera) {
+        tera.register_function("markdown", markdown);
+        tera.register_function("list_files", list_files);
+        tera.register_function("blog_posts", blog_posts);
+
+        // `forum_latest(limit=5)`: most recent threads, newest first.
+        let db = self.forum_db.clone();
+        tera.register_function("forum_latest", move |args: &HashMap<String, Value>| {
+            let limit = arg_limit(args, 10) as i64;
+            let rows: Vec<(String, String, String, String, String, i64)> = block_on(
+                sqlx::query_as(
+                    "SELECT p.id, p.title, p.author, p.category_id, p.created_at,
+                            (SELECT COUNT(*) FROM replies r WHERE r.thread_id = p.id)
+                     FROM posts p ORDER BY p.created_at DESC LIMIT ?",
+                )
+                .bind(limit)
+                .fetch_all(&*db),
+            )
+            .map_err(|e| tera::Error::msg(format!("forum_latest: {e}")))?;
+            let prefix = REQUEST
+                .try_with(|r| r.forum_prefix.clone())
+                .unwrap_or_else(|_| DEFAULT_FORUM_PREFIX.to_string());
+            let threads: Vec<Value> = rows
+                .into_iter()
+                .map(|(id, title, author, category_id, created_at, replies)| {
+                    json!({
+                        "id": id,
+                        "title": title,
+                        "author": author,
+                        "category_id": category_id,
+                        "created_at": created_at,
+                        "replies": replies,
+                        "url": format!("{prefix}/thread/{id}"),
+                    })
+                })
+                .collect();
+            Ok(Value::Array(threads))
+        });
+
+        // `current_user()`: the signed-in forum username and guard email, or
+        // null for anonymous visitors.
+        let db = self.forum_db.clone();
+        tera.register_function("current_user", move |_: &HashMap<String, Value>| {
+            let cookies = REQUEST.try_with(|r| r.cookies.clone()).unwrap_or_default();
+            let (username, email) = block_on(async {
+                let username = match forum_sessions::username_from_header(&db, &cookies).await {
+                    Some(name) => sqlx::query_scalar::<_, String>(
+                        "SELECT username FROM users WHERE username = ? AND is_banned = 0",
+                    )
+                    .bind(&name)
+                    .fetch_optional(&*db)
+                    .await
+                    .ok()
+                    .flatten(),
+                    None => None,
+                };
+                let email = match auth_guard::extract_cookie_value(&cookies, auth_guard::GUARD_COOKIE) {
+                    Some(token) => auth_guard::validate_session(&db, &token).await,
+                    None => None,
+                };
+                (username, email)
+            });
+            if username.is_none() && email.is_none() {
+                return Ok(Value::Null);
+            }
+            Ok(json!({ "username": username, "email": email }))
+        });
+
+        // `stream_status()`: whether a WHIP broadcast is live, who is
+        // broadcasting and how many WHEP viewers are connected.
+        let stream = self.stream.clone();
+        tera.register_function("stream_status", move |_: &HashMap<String, Value>| {
+            let live = stream.is_live.load(Ordering::Relaxed);
+            let (broadcaster, viewers) = block_on(async {
+                (stream.broadcaster_username.read().await.clone(), stream.viewer_count().await)
+            });
+            Ok(json!({ "live": live, "broadcaster": broadcaster, "viewers": viewers }))
+        });
+    }
+}
@@ -0,0 +1,176 @@
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use solarized::{
+    print_fancy,
+    BLUE, CYAN, GREEN, RED,
+    BOLD,
+    PrintMode::NewLine,
+};
+use tera::{Context, Tera};
+
+use crate::template_functions::TemplateFunctions;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+fn log_info(msg: &str) {
+    print_fancy(&[("[templates] ", BLUE, vec![]), (msg, CYAN, vec![])], NewLine);
+}
+
+fn log_ok(msg: &str) {
+    print_fancy(&[("[templates] ", BLUE, vec![]), (msg, GREEN, vec![BOLD])], NewLine);
+}
+
+fn log_err(msg: &str) {
+    print_fancy(&[("[templates] ", BLUE, vec![]), (msg, RED, vec![BOLD])], NewLine);
+}
+
+/// An error and every `source()` below it, one per line. Tera puts the
+/// template name in the outer message and the line/column of a syntax error
+/// in the parser error underneath.
+fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut msg = err.to_string();
+    let mut source = err.source();
+    while let Some(s) = source {
+        msg.push('\n');
+        msg.push_str(&s.to_string());
+        source = s.source();
+    }
+    msg
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// The compiled templates shared by every handler. With `dev_mode` they are
+/// rebuilt whenever a file under static/ changes, and render failures are
+/// shown in the browser instead of a bare 500.
+pub struct Templates {
+    tera: RwLock<Tera>,
+    functions: TemplateFunctions,
+    dev_mode: bool,
+    /// Why the last rebuild failed; the previous templates stay in use.
+    reload_error: RwLock<Option<String>>,
+}
+
+impl Templates {
+    pub fn new(mut tera: Tera, functions: TemplateFunctions, dev_mode: bool) -> Self {
+        functions.register(&mut tera);
+        Self { tera: RwLock::new(tera), functions, dev_mode, reload_error: RwLock::new(None) }
+    }
+
+    pub fn render(&self, name: &str, context: &Context) -> tera::Result<String> {
+        if self.dev_mode
+            && let Some(e) = self.reload_error.read().unwrap().as_ref()
+        {
+            return Err(tera::Error::msg(e.clone()));
+        }
+        self.tera.read().unwrap().render(name, context)
+    }
+
+    /// Rebuild from the embedded defaults and static/ and swap the result in.
+    /// On a parse error the current templates are kept.
+    pub fn reload(&self) -> Result<(), String> {
+        match crate::utils::load_templates() {
+            Ok(mut tera) => {
+                self.functions.register(&mut tera);
+                *self.tera.write().unwrap() = tera;
+                *self.reload_error.write().unwrap() = None;
+                Ok(())
+            }
+            Err(e) => {
+                let msg = error_chain(&e);
+                *self.reload_error.write().unwrap() = Some(msg.clone());
+                Err(msg)
+            }
+        }
+    }
+
+    /// Response for a failed `render` of `name`. Outside dev mode the error
+    /// only goes to the console.
+    pub fn error_response(&self, name: &str, err: &tera::Error) -> Response {
+        let details = error_chain(err);
+        eprintln!("Tera error rendering {name}: {details}");
+        if !self.dev_mode {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+        }
+        let disk_path = crate::paths::get().static_dir.join(name);
+        let source = if disk_path.is_file() {
+            disk_path.display().to_string()
+        } else {
+            format!("{name} (built-in)")
+        };
+        let body = format!(
+            r#"<!doctype html>
+<html lang="en-US">
+<head>
+    <meta charset="utf-8" />
+    <title>Template error</title>
+    <style>
+        body {{ background: #002b36; color: #93a1a1; font-family: monospace; padding: 2em; }}
+        h1 {{ color: #dc322f; }}
+        .file {{ color: #b58900; }}
+        pre {{ background: #073642; padding: 1em; white-space: pre-wrap; }}
+    </style>
+</head>
+<body>
+    <h1>Template error</h1>
+    <p class="file">{source}</p>
+    <pre>{details}</pre>
+    <p>dev_mode is on; refresh once the template is fixed.</p>
+</body>
+</html>"#,
+            source = escape_html(&source),
+            details = escape_html(&details),
+        );
+        (StatusCode::INTERNAL_SERVER_ERROR, Html(body)).into_response()
+    }
+}
+
+/// Newest mtime and number of `.html` files under `dir`, so edits, new files
+/// and deletions all change it.
+fn fingerprint(dir: &Path) -> (Option<SystemTime>, usize) {
+    let mut newest = None;
+    let mut count = 0;
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return (newest, count);
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            let (m, c) = fingerprint(&path);
+            newest = newest.max(m);
+            count += c;
+        } else if path.extension().is_some_and(|ext| ext == "html") {
+            newest = newest.max(entry.metadata().and_then(|m| m.modified()).ok());
+            count += 1;
+        }
+    }
+    (newest, count)
+}
+
+/// dev_mode: poll the static directory and rebuild the templates whenever it changes.
+pub async fn watch(templates: Arc<Templates>) {
+    let dir = crate::paths::get().static_dir.as_path();
+    log_info(&format!("dev_mode on; watching {} for template changes", dir.display()));
+    let mut last_seen = fingerprint(dir);
+    let mut ticker = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let seen = fingerprint(dir);
+        if seen == last_seen {
+            continue;
+        }
+        last_seen = seen;
+        match templates.reload() {
+            Ok(()) => log_ok("templates reloaded"),
+            Err(e) => log_err(&format!("template error, keeping previous templates: {e}")),
+        }
+    }
+}
@@ -12,8 +12,8 @@ const THUMBNAIL_WIDTH: u32 = 150;
 const THUMBNAIL_HEIGHT: u32 = 150;
 
 pub async fn generate_thumbnail(Path(path): Path<String>) -> impl IntoResponse {
-    let cache_dir = ".thumb_cache";
-    let cache_path = format!("{}/{}", cache_dir, path.replace("/", "_"));
+    let cache_dir = &crate::paths::get().thumb_cache;
+    let cache_path = cache_dir.join(path.replace("/", "_"));
     if let Ok(cached_data) = tokio::fs::read(&cache_path).await {
         return Response::builder()
             .header(header::CONTENT_TYPE, "image/png")
@@ -21,7 +21,7 @@ pub async fn generate_thumbnail(Path(path): Path<String>) -> impl IntoResponse {
             .body(Body::from(cached_data))
             .unwrap();
     }
-    let image_bytes = match tokio::fs::read(&path).await {
+    let image_bytes = match tokio::fs::read(crate::paths::config_path(&path)).await {
         Ok(bytes) => bytes,
         Err(_) => {
             println!("ERROR: Could not find image at path: {}", &path);
@@ -4,6 +4,8 @@ use axum::{
     http::{HeaderMap, StatusCode},
 };
 use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use bytes::Bytes;
 use tokio::{
     fs::{self, File},
@@ -13,6 +15,8 @@ use sanitize_filename;
 use walkdir::WalkDir;
 use serde_json::json;
 
+use crate::metrics::Metrics;
+
 enum UploadResponse {
     Html(Html<String>),
     Json(Json<serde_json::Value>),
@@ -44,7 +48,7 @@ async fn get_directory_size<P: AsRef<Path>>(path: P) -> u64 {
     }).await.unwrap_or(0)
 }
 
-pub async fn upload(headers: HeaderMap, mut multipart: Multipart, upload_storage_limit: Option<u64>) -> impl IntoResponse {
+pub async fn upload(headers: HeaderMap, mut multipart: Multipart, upload_storage_limit: Option<u64>, metrics: Arc<Metrics>) -> impl IntoResponse {
     let wants_json = headers
         .get("accept")
         .and_then(|v| v.to_str().ok())
@@ -58,7 +62,7 @@ pub async fn upload(headers: HeaderMap, mut multipart: Multipart, upload_storage
             Ok(mut field) => {
                 if let Some(filename) = field.file_name() {
                     let sanitized_filename = sanitize_filename::sanitize(filename);
-                    let filepath = Path::new("./uploads").join(&sanitized_filename);
+                    let filepath = crate::paths::get().uploads.join(&sanitized_filename);
                     
                     if let Some(parent) = filepath.parent() {
                         if let Err(e) = fs::create_dir_all(parent).await {
@@ -87,7 +91,7 @@ pub async fn upload(headers: HeaderMap, mut multipart: Multipart, upload_storage
                         match chunk {
                             Ok(data) => {
                                 let data = Bytes::from(data);
-                                let uploads_path = Path::new("./uploads");
+                                let uploads_path = crate::paths::get().uploads.as_path();
                                 let current_size = get_directory_size(uploads_path).await;
                                 if let Some(limit) = upload_storage_limit {
                                     let new_size = current_size + data.len() as u64;
@@ -110,6 +114,7 @@ pub async fn upload(headers: HeaderMap, mut multipart: Multipart, upload_storage
                                         return UploadResponse::Html(Html(error_html("Failed to write to file")));
                                     }
                                 }
+                                metrics.upload_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
                             },
                             Err(e) => {
                                 eprintln!("Failed to read chunk: {:?}", e);
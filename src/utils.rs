@@ -1,25 +1,31 @@
 use tokio::fs;
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::sync::Arc;
 use rustls::ServerConfig;
-use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::danger::ClientCertVerifier;
 
-pub async fn build_tls_config(cert_path: &str, key_path: &str) -> anyhow::Result<Arc<ServerConfig>> {
-    let cert_pem = tokio::fs::read(cert_path).await?;
-    let key_pem = tokio::fs::read(key_path).await?;
+use tera::Tera;
 
-    let certs: Vec<CertificateDer<'static>> = rustls_pemfile::certs(&mut cert_pem.as_slice())
-        .collect::<Result<Vec<_>, _>>()?;
+use crate::certs::{CertResolver, ACME_TLS_ALPN};
+use crate::constants::EMBEDDED_TEMPLATES;
 
-    let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut key_pem.as_slice())?
-        .ok_or_else(|| anyhow::anyhow!("no private key found in {key_path}"))?;
+/// HTTPS server config that picks the certificate per connection via SNI and,
+/// with a `client_verifier`, asks clients for an optional certificate.
+pub fn build_tls_config(
+    resolver: Arc<CertResolver>,
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+) -> Arc<ServerConfig> {
+    let builder = ServerConfig::builder();
+    let builder = match client_verifier {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_cert_resolver(resolver);
 
-    let mut config = ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), ACME_TLS_ALPN.to_vec()];
 
-    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
-
-    Ok(Arc::new(config))
+    Arc::new(config)
 }
 
 pub async fn read_media_files(dir: &str) -> std::io::Result<Vec<String>> {
@@ -32,3 +38,46 @@ pub async fn read_media_files(dir: &str) -> std::io::Result<Vec<String>> {
     }
     Ok(files)
 }
+
+/// Add every `.html` file below `dir` under its path relative to `root`, the
+/// names `Tera::new("static/**/*.html")` would give them.
+fn collect_templates(root: &Path, dir: &Path, out: &mut BTreeMap<String, String>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_templates(root, &path, out)?;
+        } else if path.extension().is_some_and(|ext| ext == "html") {
+            let name = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            out.insert(name, std::fs::read_to_string(&path)?);
+        }
+    }
+    Ok(())
+}
+
+/// The built-in templates with `static/**/*.html` (or the configured
+/// `static_dir`) layered on top: a file on
+/// disk replaces the embedded template of the same name, so a site can
+/// override `base.html` alone and keep the rest. Both layers are parsed
+/// together, so a disk template may extend an embedded one and vice versa.
+pub fn load_templates() -> tera::Result<Tera> {
+    let mut templates: BTreeMap<String, String> = EMBEDDED_TEMPLATES
+        .iter()
+        .map(|(name, body)| (name.to_string(), body.to_string()))
+        .collect();
+    let root = crate::paths::get().static_dir.as_path();
+    if root.is_dir() {
+        collect_templates(root, root, &mut templates).map_err(|e| {
+            tera::Error::chain(format!("Failed to read templates under {}", root.display()), e)
+        })?;
+    }
+    let mut tera = Tera::default();
+    tera.add_raw_templates(templates)?;
+    tera.autoescape_on(vec![]);
+    Ok(tera)
+}